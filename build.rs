@@ -0,0 +1,239 @@
+//! Compiles builtin magic (`magic.ktrace`) into Rust code, constructing its definitions,
+//! so it does not have to be parsed at runtime.
+//! Magic is loaded with the same code lxtrace uses for user-supplied files.
+use std::{fmt::Write, path::PathBuf};
+
+// `magic` module is included from library sources; build script doesn't use everything
+#[allow(dead_code, unused_imports)]
+#[path = "src"]
+mod lib {
+    pub(crate) mod magic;
+}
+// library code refers to `crate::magic`
+use lib::magic;
+
+use magic::hir::{
+    ConstDef, DefBody, EnumDef, FieldDef, FieldDirection, FieldTypeInfo, FlagsDef, ItemDef,
+    SourceLoc, StructDef, SyscallAnalyzeStrategy, SyscallDef, SyscallGroup, SyscallId,
+};
+
+/// Writes Rust expression, which evaluates to the same value
+trait Emit {
+    fn emit(&self, out: &mut String);
+}
+
+impl Emit for String {
+    fn emit(&self, out: &mut String) {
+        write!(out, "String::from({:?})", self).unwrap();
+    }
+}
+
+impl Emit for bool {
+    fn emit(&self, out: &mut String) {
+        write!(out, "{}", self).unwrap();
+    }
+}
+
+impl Emit for u64 {
+    fn emit(&self, out: &mut String) {
+        write!(out, "{}", self).unwrap();
+    }
+}
+
+impl Emit for usize {
+    fn emit(&self, out: &mut String) {
+        write!(out, "{}", self).unwrap();
+    }
+}
+
+impl<T: Emit> Emit for Option<T> {
+    fn emit(&self, out: &mut String) {
+        match self {
+            Some(val) => {
+                out.push_str("Some(");
+                val.emit(out);
+                out.push(')');
+            }
+            None => out.push_str("None"),
+        }
+    }
+}
+
+impl<T: Emit> Emit for Vec<T> {
+    fn emit(&self, out: &mut String) {
+        out.push_str("vec![");
+        for (i, item) in self.iter().enumerate() {
+            if i != 0 {
+                out.push_str(", ");
+            }
+            item.emit(out);
+        }
+        out.push(']');
+    }
+}
+
+/// Emits struct literal, e.g. `emit_struct!(out, Foo, self, a, b)` emits `Foo { a: .., b: .. }`
+macro_rules! emit_struct {
+    ($out: expr, $name: ident, $val: expr, $($field: ident),*) => {{
+        $out.push_str(concat!(stringify!($name), " {"));
+        $(
+            $out.push_str(concat!(stringify!($field), ": "));
+            $val.$field.emit($out);
+            $out.push_str(", ");
+        )*
+        $out.push('}');
+    }};
+}
+
+impl Emit for SourceLoc {
+    fn emit(&self, out: &mut String) {
+        // `file` variable is defined in generated function
+        write!(
+            out,
+            "SourceLoc {{ file: file.clone(), line: {}, column: {} }}",
+            self.line, self.column
+        )
+        .unwrap();
+    }
+}
+
+impl Emit for FieldDirection {
+    fn emit(&self, out: &mut String) {
+        out.push_str(match self {
+            FieldDirection::In => "FieldDirection::In",
+            FieldDirection::Out => "FieldDirection::Out",
+            FieldDirection::InOut => "FieldDirection::InOut",
+        });
+    }
+}
+
+impl Emit for FieldTypeInfo {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(
+            out,
+            FieldTypeInfo,
+            self,
+            ty_name,
+            len_ref,
+            size,
+            total_len_ref
+        );
+    }
+}
+
+impl Emit for FieldDef {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, FieldDef, self, name, ty_info, dir, offset, doc, loc);
+    }
+}
+
+impl Emit for DefBody {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, DefBody, self, fields, decode_order);
+    }
+}
+
+impl Emit for ConstDef {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, ConstDef, self, name, value, mask, doc);
+    }
+}
+
+impl Emit for SyscallId {
+    fn emit(&self, out: &mut String) {
+        write!(out, "SyscallId({})", self.0).unwrap();
+    }
+}
+
+impl Emit for SyscallAnalyzeStrategy {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, SyscallAnalyzeStrategy, self, on_enter, on_exit);
+    }
+}
+
+impl Emit for SyscallGroup {
+    fn emit(&self, out: &mut String) {
+        write!(out, "SyscallGroup::{:?}", self).unwrap();
+    }
+}
+
+impl Emit for SyscallDef {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, SyscallDef, self, id, name, body, strategy, groups, doc, loc);
+    }
+}
+
+impl Emit for StructDef {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, StructDef, self, name, body, size, doc, loc);
+    }
+}
+
+impl Emit for FlagsDef {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, FlagsDef, self, name, flags, size, doc, loc);
+    }
+}
+
+impl Emit for EnumDef {
+    fn emit(&self, out: &mut String) {
+        emit_struct!(out, EnumDef, self, name, variants, size, doc, loc);
+    }
+}
+
+impl Emit for ItemDef {
+    fn emit(&self, out: &mut String) {
+        match self {
+            ItemDef::Syscall(def) => {
+                out.push_str("ItemDef::Syscall(");
+                def.emit(out);
+            }
+            ItemDef::Struct(def) => {
+                out.push_str("ItemDef::Struct(");
+                def.emit(out);
+            }
+            ItemDef::Flags(def) => {
+                out.push_str("ItemDef::Flags(");
+                def.emit(out);
+            }
+            ItemDef::Enum(def) => {
+                out.push_str("ItemDef::Enum(");
+                def.emit(out);
+            }
+        }
+        out.push(')');
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=magic.ktrace");
+    println!("cargo:rerun-if-changed=src/magic.pest");
+    println!("cargo:rerun-if-changed=src/magic.rs");
+    println!("cargo:rerun-if-changed=src/magic");
+    println!("cargo:rustc-cfg=builtin_magic");
+
+    let data = std::fs::read_to_string("magic.ktrace").expect("failed to read magic.ktrace");
+    let magic = match magic::init(&data) {
+        Ok(magic) => magic,
+        Err(err) => {
+            eprintln!("invalid builtin magic: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut out = String::new();
+    out.push_str("// Generated by build.rs from magic.ktrace\n\n");
+    out.push_str("pub(super) fn defs() -> Vec<ItemDef> {\n");
+    out.push_str("    let file: Arc<str> = Arc::from(super::BUILTIN_NAME);\n");
+    out.push_str("    vec![\n");
+    for def in magic.defs() {
+        out.push_str("        ");
+        def.emit(&mut out);
+        out.push_str(",\n");
+    }
+    out.push_str("    ]\n}\n");
+
+    let out_path = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("builtin_magic.rs");
+    std::fs::write(out_path, out).expect("failed to write generated magic");
+}
@@ -55,7 +55,7 @@ impl Symbol {
         self.name.as_ref()
     }
 
-    pub fn demangle(&self) -> Cow<str> {
+    pub fn demangle(&self) -> Cow<'_, str> {
         if let Ok(rust_sym) = rustc_demangle::try_demangle(self.raw_name()) {
             return Cow::Owned(rust_sym.as_str().to_string());
         }
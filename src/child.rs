@@ -10,30 +10,68 @@ pub struct SpawnOptions<'a> {
 pub enum Payload<'a> {
     Fn(Box<dyn FnOnce() + Send>),
     Cmd(SpawnOptions<'a>),
+    /// Attach to already running process with given pid (and all its threads).
+    /// Process is not killed when tracing is finished.
+    Attach(u32),
 }
 
 fn payload_cmd(spawn_opts: SpawnOptions) -> ! {
-    if let Err(e) = nix::unistd::execvpe(spawn_opts.exe, spawn_opts.argv, spawn_opts.env) {
-        let err_msg = e.to_string();
-        nix::unistd::write(1, err_msg.as_bytes()).ok();
-        unsafe {
-            libc::exit(libc::EXIT_FAILURE);
-        }
+    // Ok is never returned, because it contains Void value
+    let Err(e) = nix::unistd::execvpe(spawn_opts.exe, spawn_opts.argv, spawn_opts.env);
+    let err_msg = e.to_string();
+    nix::unistd::write(1, err_msg.as_bytes()).ok();
+    unsafe { libc::exit(libc::EXIT_FAILURE) }
+}
+
+/// Installs seccomp filter. It must be done after tracer has set PTRACE_O_TRACESECCOMP,
+/// otherwise traced syscalls would fail with ENOSYS.
+unsafe fn install_seccomp_filter(prog: &[libc::sock_filter]) -> nix::Result<()> {
+    let fprog = libc::sock_fprog {
+        len: prog.len() as u16,
+        filter: prog.as_ptr() as *mut _,
+    };
+    let set_filter = || {
+        libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER,
+            &fprog as *const libc::sock_fprog,
+        )
+    };
+    if set_filter() == 0 {
+        return Ok(());
     }
-    // safety: Ok is never returned, because it contains Void value
-    unsafe { std::hint::unreachable_unchecked() }
+    // without CAP_SYS_ADMIN, seccomp requires no_new_privs
+    if nix::errno::Errno::last() != nix::errno::Errno::EACCES
+        || libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+        || set_filter() != 0
+    {
+        return Err(nix::Error::last());
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn execute_child_payload(action: Payload) -> ! {
+/// If `seccomp_prog` is given, tracer relies on it to stop only on interesting syscalls
+pub(crate) unsafe fn execute_child_payload(
+    action: Payload,
+    seccomp_prog: Option<&[libc::sock_filter]>,
+) -> ! {
     ptrace::traceme().expect("ptrace(TRACEME) failed");
     if libc::raise(libc::SIGSTOP) == -1 {
         panic!("raise(SIGSTOP)")
     }
+    if let Some(prog) = seccomp_prog {
+        if let Err(e) = install_seccomp_filter(prog) {
+            let err_msg = format!("failed to install seccomp filter: {}", e);
+            nix::unistd::write(2, err_msg.as_bytes()).ok();
+            libc::exit(libc::EXIT_FAILURE)
+        }
+    }
     match action {
         Payload::Fn(bfn) => {
             bfn();
         }
         Payload::Cmd(spawn_opts) => payload_cmd(spawn_opts),
+        Payload::Attach(_) => unreachable!("attach payload does not spawn child"),
     }
     libc::exit(0)
 }
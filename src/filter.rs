@@ -0,0 +1,179 @@
+//! Syscall filter, specified by `--trace` expressions
+use crate::{
+    magic::{hir::SyscallId, Magic},
+    AUDIT_ARCH_X86_64,
+};
+use anyhow::Context;
+use std::collections::HashSet;
+
+/// Set of syscalls which should be reported.
+/// Syscalls outside of this set are not decoded at all.
+pub(crate) struct SyscallFilter {
+    ids: HashSet<SyscallId>,
+    /// If true, `ids` contains syscalls which should be suppressed
+    negated: bool,
+}
+
+impl SyscallFilter {
+    /// Filter which matches all syscalls
+    pub(crate) fn all() -> SyscallFilter {
+        SyscallFilter {
+            ids: HashSet::new(),
+            negated: true,
+        }
+    }
+
+    /// Parses filter expression.
+    /// Expression is comma-separated list of items, where each item is one of:
+    /// - syscall name (e.g. `open`)
+    /// - syscall group (e.g. `%file`)
+    /// - regex, matched against syscall names (e.g. `/^rt_`)
+    /// - `all`
+    ///
+    /// Leading `!` inverts whole expression.
+    /// Several expressions are combined: syscall is reported if any of them matches.
+    pub(crate) fn parse(exprs: &[String], magic: &Magic) -> anyhow::Result<SyscallFilter> {
+        if exprs.is_empty() {
+            return Ok(SyscallFilter::all());
+        }
+        let mut included = HashSet::new();
+        let mut excluded: Option<HashSet<SyscallId>> = None;
+        for expr in exprs {
+            let (negated, list) = match expr.strip_prefix('!') {
+                Some(list) => (true, list),
+                None => (false, expr.as_str()),
+            };
+            let mut ids = HashSet::new();
+            for item in list.split(',') {
+                ids.extend(
+                    resolve_item(item.trim(), magic)
+                        .with_context(|| format!("invalid trace expression `{}`", expr))?,
+                );
+            }
+            if negated {
+                // syscall passes union of negated sets unless it is excluded by all of them
+                excluded = Some(match excluded {
+                    Some(prev) => prev.intersection(&ids).cloned().collect(),
+                    None => ids,
+                });
+            } else {
+                included.extend(ids);
+            }
+        }
+        Ok(match excluded {
+            Some(excluded) => SyscallFilter {
+                ids: excluded.difference(&included).cloned().collect(),
+                negated: true,
+            },
+            None => SyscallFilter {
+                ids: included,
+                negated: false,
+            },
+        })
+    }
+
+    pub(crate) fn matches(&self, id: &SyscallId) -> bool {
+        self.ids.contains(id) != self.negated
+    }
+
+    /// Returns filter, which additionally matches `extra` syscalls
+    pub(crate) fn with_syscalls<'a>(
+        &self,
+        extra: impl Iterator<Item = &'a SyscallId>,
+    ) -> SyscallFilter {
+        let mut ids = self.ids.clone();
+        for id in extra {
+            if self.negated {
+                ids.remove(id);
+            } else {
+                ids.insert(id.clone());
+            }
+        }
+        SyscallFilter {
+            ids,
+            negated: self.negated,
+        }
+    }
+
+    /// Returns seccomp program, which makes kernel stop tracee only on syscalls matched
+    /// by this filter, or None if all syscalls are matched anyway.
+    pub(crate) fn seccomp_program(&self) -> Option<Vec<libc::sock_filter>> {
+        if self.negated && self.ids.is_empty() {
+            return None;
+        }
+        let (on_match, on_mismatch) = if self.negated {
+            (libc::SECCOMP_RET_ALLOW, libc::SECCOMP_RET_TRACE)
+        } else {
+            (libc::SECCOMP_RET_TRACE, libc::SECCOMP_RET_ALLOW)
+        };
+        let mut ids = self.ids.iter().map(|id| id.0).collect::<Vec<_>>();
+        ids.sort_unstable();
+        let mut prog = vec![
+            // syscalls of foreign architectures are always traced
+            bpf_stmt(
+                libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                SECCOMP_DATA_ARCH,
+            ),
+            bpf_jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH_X86_64,
+                1,
+                0,
+            ),
+            bpf_stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_TRACE),
+            bpf_stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, SECCOMP_DATA_NR),
+        ];
+        // jump offsets are 8-bit, so each comparison gets its own return
+        for id in ids {
+            prog.push(bpf_jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                id,
+                0,
+                1,
+            ));
+            prog.push(bpf_stmt(libc::BPF_RET | libc::BPF_K, on_match));
+        }
+        prog.push(bpf_stmt(libc::BPF_RET | libc::BPF_K, on_mismatch));
+        Some(prog)
+    }
+}
+
+/// Offsets of fields in `struct seccomp_data`
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+fn bpf_stmt(code: u32, k: u32) -> libc::sock_filter {
+    bpf_jump(code, k, 0, 0)
+}
+
+fn bpf_jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+pub(crate) fn resolve_item(item: &str, magic: &Magic) -> anyhow::Result<Vec<SyscallId>> {
+    if item.is_empty() {
+        anyhow::bail!("empty syscall specification");
+    }
+    if item == "all" {
+        return Ok(magic.syscalls().map(|def| def.id.clone()).collect());
+    }
+    if let Some(pattern) = item.strip_prefix('/') {
+        let regex =
+            regex::Regex::new(pattern).with_context(|| format!("invalid regex: {}", pattern))?;
+        let ids = magic
+            .syscalls()
+            .filter(|def| regex.is_match(&def.name))
+            .map(|def| def.id.clone())
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            anyhow::bail!("regex {} does not match any syscall", pattern);
+        }
+        return Ok(ids);
+    }
+    magic.resolve_syscall_set(item)
+}
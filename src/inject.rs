@@ -0,0 +1,135 @@
+//! Syscall fault injection, specified by `--inject` rules
+use crate::{filter::resolve_item, magic::hir::SyscallId, magic::Magic};
+use anyhow::Context;
+use std::collections::HashSet;
+
+/// Which invocations of syscall are affected by rule.
+/// Invocations are numbered from 1, separately for each process (threads share
+/// counters) and syscall
+struct When {
+    first: u64,
+    /// None if only `first` invocation is affected
+    step: Option<u64>,
+}
+
+impl When {
+    /// Parses `N` (only N-th invocation), `N+` (N-th and all following)
+    /// or `N+S` (N-th and every S-th after it)
+    fn parse(s: &str) -> anyhow::Result<When> {
+        let parse_num = |num: &str| -> anyhow::Result<u64> {
+            match num.parse::<u64>() {
+                Ok(0) => anyhow::bail!("invocation numbers start from 1"),
+                Ok(num) => Ok(num),
+                Err(_) => anyhow::bail!("invalid number: {}", num),
+            }
+        };
+        let (first, step) = match s.find('+') {
+            Some(pos) => {
+                let step = match &s[pos + 1..] {
+                    "" => 1,
+                    step => parse_num(step)?,
+                };
+                (&s[..pos], Some(step))
+            }
+            None => (s, None),
+        };
+        Ok(When {
+            first: parse_num(first)?,
+            step,
+        })
+    }
+
+    fn matches(&self, invocation: u64) -> bool {
+        if invocation < self.first {
+            return false;
+        }
+        match self.step {
+            Some(step) => (invocation - self.first).is_multiple_of(step),
+            None => invocation == self.first,
+        }
+    }
+}
+
+struct InjectRule {
+    ids: HashSet<SyscallId>,
+    errno: i32,
+    when: When,
+}
+
+/// Set of rules, which make syscalls fail without executing them
+pub(crate) struct Injector {
+    rules: Vec<InjectRule>,
+}
+
+fn parse_errno(s: &str) -> anyhow::Result<i32> {
+    if let Ok(errno) = s.parse::<i32>() {
+        if !(1..4096).contains(&errno) {
+            anyhow::bail!("errno {} is out of range", errno);
+        }
+        return Ok(errno);
+    }
+    (1..4096)
+        .find(|&errno| crate::errno_name(errno) == s)
+        .ok_or_else(|| anyhow::anyhow!("unknown errno: {}", s))
+}
+
+impl Injector {
+    /// Parses injection rules.
+    /// Rule has form `SYSCALLS:error=ERRNO[:when=WHEN]`, where `SYSCALLS` is comma-separated
+    /// list, as in `--trace` expression, `ERRNO` is errno name (e.g. `ENOENT`) or number,
+    /// and `WHEN` is `N`, `N+` or `N+S` (all invocations by default).
+    /// If several rules match syscall, first of them is applied.
+    pub(crate) fn parse(specs: &[String], magic: &Magic) -> anyhow::Result<Injector> {
+        let mut rules = Vec::new();
+        for spec in specs {
+            rules.push(
+                parse_rule(spec, magic)
+                    .with_context(|| format!("invalid inject rule `{}`", spec))?,
+            );
+        }
+        Ok(Injector { rules })
+    }
+
+    /// Returns true if some rule can affect syscall
+    pub(crate) fn handles(&self, id: &SyscallId) -> bool {
+        self.rules.iter().any(|rule| rule.ids.contains(id))
+    }
+
+    /// Returns all syscalls, which can be affected by rules
+    pub(crate) fn syscalls(&self) -> impl Iterator<Item = &SyscallId> {
+        self.rules.iter().flat_map(|rule| rule.ids.iter())
+    }
+
+    /// Returns errno, which should be injected into `invocation`-th call of syscall
+    pub(crate) fn errno(&self, id: &SyscallId, invocation: u64) -> Option<i32> {
+        self.rules
+            .iter()
+            .find(|rule| rule.ids.contains(id) && rule.when.matches(invocation))
+            .map(|rule| rule.errno)
+    }
+}
+
+fn parse_rule(spec: &str, magic: &Magic) -> anyhow::Result<InjectRule> {
+    let mut parts = spec.split(':');
+    let mut ids = HashSet::new();
+    for item in parts.next().unwrap_or_default().split(',') {
+        ids.extend(resolve_item(item.trim(), magic)?);
+    }
+    let mut errno = None;
+    let mut when = None;
+    for part in parts {
+        match part.find('=').map(|pos| (&part[..pos], &part[pos + 1..])) {
+            Some(("error", value)) => errno = Some(parse_errno(value)?),
+            Some(("when", value)) => when = Some(When::parse(value)?),
+            _ => anyhow::bail!("unknown option: {}", part),
+        }
+    }
+    Ok(InjectRule {
+        ids,
+        errno: errno.context("error is not specified")?,
+        when: when.unwrap_or(When {
+            first: 1,
+            step: Some(1),
+        }),
+    })
+}
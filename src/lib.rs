@@ -1,6 +1,9 @@
 pub mod backtrace;
 mod child;
+mod filter;
+mod inject;
 pub mod magic;
+pub mod summary;
 mod syscall_decode;
 mod tracer;
 
@@ -9,19 +12,55 @@ pub use child::{Payload, SpawnOptions};
 pub use magic::ty::Value;
 use magic::Magic;
 use serde::{Deserialize, Serialize};
-use std::{mem, os::unix::io::RawFd};
+use std::{
+    mem,
+    os::unix::io::RawFd,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tiny_nix_ipc::Socket;
 
 pub struct Settings {
     pub capture_backtrace: bool,
+    /// Additional magic files, extending or overriding builtin definitions
+    pub magic_files: Vec<PathBuf>,
+    /// Syscall filter expressions (see `--trace`), empty means all syscalls are traced
+    pub trace: Vec<String>,
+    /// Kill tracees when tracing is stopped (by SIGINT or SIGTERM, or if tracer exits).
+    /// Otherwise tracer detaches from them, leaving them running (unless `seccomp` is used)
+    pub kill_on_exit: bool,
+    /// Fault injection rules (see `--inject`), e.g. `open:error=ENOENT:when=3+`
+    pub inject: Vec<String>,
+    /// Run spawned tracee under seccomp filter, so that it is stopped only on traced syscalls.
+    /// Filter can not be removed, and without tracer filtered syscalls would fail with `ENOSYS`,
+    /// so such tracees are always killed when tracing is stopped. Therefore filter is used
+    /// automatically only with `kill_on_exit`, this option enables it in other cases too.
+    /// If tracer lacks `CAP_SYS_ADMIN`, tracee gets `no_new_privs` flag (e.g. setuid
+    /// executables lose their privileges). Ignored when attaching to running process
+    pub seccomp: bool,
+    /// Report both entry and exit of every traced syscall. Otherwise entry is reported only
+    /// for syscalls with `#[kind = in|inout]`, and exit only for `#[kind = out|inout]` ones
+    /// (or if error was injected)
+    pub report_all_stops: bool,
 }
 
+/// Value of `RawSyscall::arch` for x86_64 syscalls (`AUDIT_ARCH_X86_64` from `<linux/audit.h>`).
+/// Syscalls of other architectures (e.g. made with `int 0x80`) are not decoded
+pub const AUDIT_ARCH_X86_64: u32 = 0xC000_003E;
+
 #[repr(C)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RawSyscall {
     pub syscall_id: u64,
     pub args: [u64; 6],
     pub ret: u64,
+    /// Syscall ABI, as `AUDIT_ARCH_*` constant
+    pub arch: u32,
+    /// Instruction pointer
+    pub ip: u64,
+    /// Stack pointer
+    pub sp: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,6 +74,16 @@ pub struct Syscall {
     pub backtrace: Option<backtrace::Backtrace>,
 }
 
+/// How new tracee was created
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpawnKind {
+    Fork,
+    Vfork,
+    /// New thread or process, created with `clone`
+    Clone,
+}
+
 #[repr(C)]
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "kind", content = "data")]
@@ -46,16 +95,42 @@ pub enum EventPayload {
         raw: RawSyscall,
         /// Second field - parsed data
         decoded: Option<Syscall>,
+        /// Syscall will not be executed, because error is injected by `Settings::inject` rule
+        injected: bool,
     },
     Sysexit {
         raw: RawSyscall,
         decoded: Option<Syscall>,
+        /// Time elapsed since syscall entry, None if entry was not observed
+        duration: Option<Duration>,
+        /// Syscall was not executed, `raw.ret` is error injected by `Settings::inject` rule
+        injected: bool,
     },
     Signal {
         raw: i32,
         decoded: String,
     },
     Exit(i32),
+    /// Tracee created new process or thread.
+    /// It will be traced too, `Attach` event is reported when it first stops
+    Spawn {
+        parent: u32,
+        child: u32,
+        kind: SpawnKind,
+    },
+    /// Tracee successfully called `execve`. If it was called by non-leader thread, `old_pid`
+    /// is id of that thread, which now has pid of thread group leader
+    Exec {
+        old_pid: u32,
+        /// Path to new executable
+        path: String,
+        argv: Vec<String>,
+    },
+    /// Tracee was terminated by signal
+    Killed {
+        signal: i32,
+        core_dumped: bool,
+    },
     /// Internal
     /// for this event pid=0
     /// tracer is about to exit because all tracees have finished
@@ -64,27 +139,121 @@ pub enum EventPayload {
     __NonExhaustive,
 }
 
+/// Moment when event was observed by tracer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Timestamp {
+    /// Time since some unspecified point (`CLOCK_MONOTONIC`), use it to measure intervals
+    pub monotonic: Duration,
+    /// Time since UNIX epoch
+    pub wall: Duration,
+}
+
+impl Timestamp {
+    pub fn now() -> Timestamp {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // CLOCK_MONOTONIC is always supported
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+        Timestamp {
+            monotonic: Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32),
+            wall: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Event {
     pub payload: EventPayload,
     pub pid: u32,
+    /// Thread group id, i.e. pid of process which thread `pid` belongs to
+    pub tgid: u32,
+    pub time: Timestamp,
+}
+
+/// Pids of tracer processes, started by `run` and not finished yet
+static TRACERS: Mutex<Vec<libc::pid_t>> = Mutex::new(Vec::new());
+
+/// Removes tracer from `TRACERS` when `run` returns
+struct TracerRegistration(libc::pid_t);
+
+impl Drop for TracerRegistration {
+    fn drop(&mut self) {
+        TRACERS.lock().unwrap().retain(|&pid| pid != self.0);
+    }
+}
+
+/// Asks all running tracers to detach from their tracees (or kill them, if
+/// `Settings::kill_on_exit` or `Settings::seccomp` is set) and finish, same as sending
+/// them SIGTERM.
+/// Pending `run` calls return when remaining events are delivered.
+pub fn stop_all() {
+    for &pid in TRACERS.lock().unwrap().iter() {
+        nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(pid),
+            nix::sys::signal::Signal::SIGTERM,
+        )
+        .ok();
+    }
 }
 
-unsafe fn split(payload: Payload, settings: Settings, out: Socket, magic: &Magic) -> ! {
+unsafe fn split(
+    payload: Payload,
+    settings: Settings,
+    out: Socket,
+    magic: &Magic,
+    filter: &filter::SyscallFilter,
+    injector: &inject::Injector,
+    mut tracee_mask: nix::sys::signal::SigSet,
+) -> ! {
+    if let Payload::Attach(pid) = payload {
+        // tracee is already running, so there is nothing to fork
+        let pid = nix::unistd::Pid::from_raw(pid as i32);
+        if let Err(err) = tracer::parent(out, settings, magic, filter, injector, false, Some(pid)) {
+            eprintln!("tracing failed: {:?}", err);
+        }
+        libc::exit(0);
+    }
+    // tracee under seccomp filter can not be left running, so filter is only used
+    // automatically when tracee would be killed anyway
+    let seccomp_prog = if settings.seccomp || settings.kill_on_exit {
+        // syscalls with injected faults must stop tracee even if they are not reported
+        filter.with_syscalls(injector.syscalls()).seccomp_program()
+    } else {
+        None
+    };
     let res = libc::fork();
     if res == -1 {
         libc::exit(1);
     }
     if res != 0 {
-        tracer::parent(out, settings, magic).ok();
+        let use_seccomp = seccomp_prog.is_some();
+        if let Err(err) = tracer::parent(out, settings, magic, filter, injector, use_seccomp, None)
+        {
+            eprintln!("tracing failed: {:?}", err);
+        }
     } else {
         mem::forget(out);
-        child::execute_child_payload(payload);
+        // caller could block these signals to handle them in dedicated thread,
+        // but tracee should get default mask
+        tracee_mask.remove(nix::sys::signal::Signal::SIGINT);
+        tracee_mask.remove(nix::sys::signal::Signal::SIGTERM);
+        tracee_mask.thread_set_mask().ok();
+        child::execute_child_payload(payload, seccomp_prog.as_deref());
     }
     libc::exit(0);
 }
 
+/// Runs `payload` under tracing, sending events to `out`.
+/// Blocks until all tracees have finished.
+///
+/// # Safety
+/// Calls `fork()`, so it must be called when no other threads
+/// can hold locks needed by the forked process.
 pub unsafe fn run(
     payload: Payload,
     settings: Settings,
@@ -94,15 +263,44 @@ pub unsafe fn run(
         .map_err(|err| anyhow!("{}", err))
         .context("failed to create socket pair")?;
 
-    let magic = magic_init();
+    let user_magic;
+    let magic = if settings.magic_files.is_empty() {
+        magic::builtin()
+    } else {
+        user_magic =
+            magic::init_from_files(&settings.magic_files).context("failed to load magic")?;
+        &user_magic
+    };
+    let filter = filter::SyscallFilter::parse(&settings.trace, magic)?;
+    let injector = inject::Injector::parse(&settings.inject, magic)?;
+    if let Payload::Attach(pid) = payload {
+        if !std::path::Path::new(&format!("/proc/{}", pid)).exists() {
+            anyhow::bail!("process {} does not exist", pid);
+        }
+    }
 
+    // tracer receives SIGINT, SIGTERM and SIGCHLD with sigtimedwait(), so they are blocked
+    // before fork, and stop request sent before tracer is ready stays pending
+    let caller_mask = tracer::tracer_signals()
+        .thread_swap_mask(nix::sys::signal::SigmaskHow::SIG_BLOCK)
+        .context("failed to block signals")?;
+    // `stop_all` waits until tracer is registered, so it can not miss it
+    let mut tracers = TRACERS.lock().unwrap();
     let res = libc::fork();
     if res == -1 {
-        return Err(anyhow::Error::new(std::io::Error::last_os_error()).context("fork failed"));
+        let err = std::io::Error::last_os_error();
+        caller_mask.thread_set_mask().ok();
+        return Err(anyhow::Error::new(err).context("fork failed"));
     }
 
     if res != 0 {
         mem::forget(snd);
+        tracers.push(res);
+        drop(tracers);
+        let _registration = TracerRegistration(res);
+        caller_mask
+            .thread_set_mask()
+            .context("failed to restore signal mask")?;
         loop {
             let msg = rcv
                 .recv_json::<Event, [RawFd; 0]>(16384)
@@ -122,12 +320,58 @@ pub unsafe fn run(
         }
     } else {
         mem::forget(rcv);
-        split(payload, settings, snd, &magic)
+        // lock is owned by parent process
+        mem::forget(tracers);
+        split(
+            payload,
+            settings,
+            snd,
+            magic,
+            &filter,
+            &injector,
+            caller_mask,
+        )
     }
 }
 
-static MAGIC: &str = include_str!("../magic.ktrace");
+pub fn magic_init() -> anyhow::Result<magic::Magic> {
+    magic::init_from_files(&[] as &[&str])
+}
+
+/// Errors, which are used inside kernel and never returned to userspace, but can be seen
+/// by tracer if syscall is interrupted by signal (see `include/linux/errno.h`)
+const KERNEL_ERRNOS: [(i32, &str, &str); 5] = [
+    (512, "ERESTARTSYS", "To be restarted if SA_RESTART is set"),
+    (513, "ERESTARTNOINTR", "To be restarted"),
+    (514, "ERESTARTNOHAND", "To be restarted if no handler"),
+    (515, "ENOIOCTLCMD", "No ioctl command"),
+    (516, "ERESTART_RESTARTBLOCK", "Interrupted by signal"),
+];
+
+/// Returns errno name (e.g. `ENOENT`), or its number if it is unknown
+pub fn errno_name(errno: i32) -> String {
+    if let Some(&(_, name, _)) = KERNEL_ERRNOS.iter().find(|(code, ..)| *code == errno) {
+        return name.to_string();
+    }
+    match nix::errno::Errno::from_i32(errno) {
+        nix::errno::Errno::UnknownErrno => errno.to_string(),
+        known => format!("{:?}", known),
+    }
+}
 
-pub fn magic_init() -> magic::Magic {
-    magic::init(MAGIC)
+/// Returns errno description, e.g. `No such file or directory`
+pub fn errno_description(errno: i32) -> String {
+    match KERNEL_ERRNOS.iter().find(|(code, ..)| *code == errno) {
+        Some(&(.., description)) => description.to_string(),
+        None => nix::errno::Errno::from_i32(errno).desc().to_string(),
+    }
+}
+
+/// Returns signal name (e.g. `SIGSEGV`), or `signal N` if it is unknown
+pub fn signal_name(signal: i32) -> String {
+    use std::convert::TryFrom;
+    match nix::sys::signal::Signal::try_from(signal) {
+        Ok(sig) => sig.as_str().to_string(),
+        Err(_) => format!("signal {}", signal),
+    }
 }
@@ -3,20 +3,74 @@ mod parse_tree;
 mod raw;
 pub mod ty;
 
+use anyhow::Context;
 use pest::Parser;
+use std::{
+    path::Path,
+    sync::{Arc, OnceLock},
+};
 
 pub use hir::Hir as Magic;
 
-pub fn init(s: &str) -> Magic {
-    let p = match raw::P::parse(raw::Rule::input, s) {
-        Ok(ast) => ast,
-        Err(err) => {
-            eprintln!("magic file syntax error: \n{}", err);
-            std::process::exit(1);
-        }
+/// Builtin magic, compiled by build script into code constructing its definitions
+#[cfg(builtin_magic)]
+mod builtin {
+    use super::hir::{
+        ConstDef, DefBody, EnumDef, FieldDef, FieldDirection, FieldTypeInfo, FlagsDef, ItemDef,
+        SourceLoc, StructDef, SyscallAnalyzeStrategy, SyscallDef, SyscallGroup, SyscallId,
     };
+    use std::sync::Arc;
+
+    include!(concat!(env!("OUT_DIR"), "/builtin_magic.rs"));
+}
+
+/// Name of builtin magic in diagnostics
+const BUILTIN_NAME: &str = "<builtin>";
+
+/// Parses and lowers single magic file. `file` is used in diagnostics
+fn parse_layer(s: &str, file: &str) -> anyhow::Result<Vec<hir::ItemDef>> {
+    let p = raw::P::parse(raw::Rule::input, s)
+        .map_err(|err| anyhow::anyhow!("syntax error:\n{}", err.with_path(file)))?;
     let parse_tree = parse_tree::new(p);
-    let mut hir = hir::lower::lower(parse_tree).expect("magic file syntax error");
-    hir::ty_collect::collect_types(&mut hir);
-    hir
+    hir::lower::lower(parse_tree, &Arc::from(file))
+}
+
+/// Loads magic from string.
+/// All definitions and types are validated, so errors are reported here rather
+/// than when syscalls are decoded.
+pub fn init(s: &str) -> anyhow::Result<Magic> {
+    let mut hir = Magic::default();
+    hir.add_layer(parse_layer(s, BUILTIN_NAME)?)?;
+    hir::ty_collect::collect_types(&mut hir, 0, true)?;
+    Ok(hir)
+}
+
+/// Returns builtin magic. It is constructed once, on first call.
+/// Builtin definitions are validated by build script, so they are not checked again.
+#[cfg(builtin_magic)]
+pub fn builtin() -> &'static Magic {
+    static BUILTIN: OnceLock<Magic> = OnceLock::new();
+    BUILTIN.get_or_init(|| {
+        let mut hir = Magic::default();
+        hir.add_layer(builtin::defs())
+            .and_then(|_| hir::ty_collect::collect_types(&mut hir, 0, false))
+            .expect("builtin magic is validated by build script");
+        hir
+    })
+}
+
+/// Loads builtin magic and extends it with definitions from `paths`.
+/// User definitions override builtin ones: syscalls are matched by id or name,
+/// types by name. Item defined in several files is reported as error.
+#[cfg(builtin_magic)]
+pub fn init_from_files(paths: &[impl AsRef<Path>]) -> anyhow::Result<Magic> {
+    let mut hir = builtin().clone();
+    for path in paths {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read magic file {}", path.display()))?;
+        hir.add_layer(parse_layer(&data, &path.display().to_string())?)?;
+    }
+    hir::ty_collect::collect_added_types(&mut hir)?;
+    Ok(hir)
 }
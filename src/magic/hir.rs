@@ -4,24 +4,159 @@ pub(super) mod ty_collect;
 mod ty_db;
 
 use super::ty::Ty;
+use std::{collections::HashMap, fmt, sync::Arc};
 
+/// Magic is built from layers: first one is builtin magic, other are user-supplied files
+#[derive(Default, Clone)]
 pub struct Hir {
     defs: Vec<ItemDef>,
+    /// For each definition, index of layer it comes from
+    def_layers: Vec<usize>,
+    layer_count: usize,
+    /// Syscall id -> index in `defs`
+    syscalls_by_id: HashMap<u32, usize>,
+    /// Syscall name -> index in `defs`
+    syscalls_by_name: HashMap<String, usize>,
+    /// Struct, flags or enum name -> index in `defs`
+    types_by_name: HashMap<String, usize>,
+    /// Some type from the first layer was overridden by other layer
+    first_layer_types_overridden: bool,
     types: ty_db::TyDb,
 }
 
 impl Hir {
-    pub fn lookup_syscall_by_id(&self, id: SyscallId) -> Option<&SyscallDef> {
-        for def in &self.defs {
-            match def {
+    /// Adds definitions from new layer.
+    /// Syscall overrides existing one with the same id or name, type overrides existing type
+    /// with the same name. Only definitions from the first layer can be overridden, so if
+    /// several layers define same item, it is reported as conflict.
+    pub(super) fn add_layer(&mut self, defs: Vec<ItemDef>) -> anyhow::Result<()> {
+        let layer = self.layer_count;
+        self.layer_count += 1;
+        for def in defs {
+            let mut matches = match &def {
                 ItemDef::Syscall(syscall_def) => {
-                    if syscall_def.id == id {
-                        return Some(syscall_def);
+                    let by_id = self.syscalls_by_id.get(&syscall_def.id.0);
+                    let by_name = self.syscalls_by_name.get(&syscall_def.name);
+                    by_id
+                        .into_iter()
+                        .chain(by_name)
+                        .copied()
+                        .collect::<Vec<_>>()
+                }
+                _ => self
+                    .types_by_name
+                    .get(def.name())
+                    .copied()
+                    .into_iter()
+                    .collect(),
+            };
+            matches.dedup();
+            match *matches.as_slice() {
+                [] => {
+                    self.defs.push(def);
+                    self.def_layers.push(layer);
+                    self.index_def(self.defs.len() - 1);
+                }
+                [i] if layer != 0 && self.def_layers[i] == 0 => {
+                    if !matches!(def, ItemDef::Syscall(_)) {
+                        self.first_layer_types_overridden = true;
                     }
+                    self.unindex_def(i);
+                    self.defs[i] = def;
+                    self.def_layers[i] = layer;
+                    self.index_def(i);
+                }
+                _ => {
+                    let existing = matches
+                        .iter()
+                        .map(|&i| format!("{} at {}", self.defs[i].describe(), self.defs[i].loc()))
+                        .collect::<Vec<_>>();
+                    anyhow::bail!(
+                        "{}: {} conflicts with {}",
+                        def.loc(),
+                        def.describe(),
+                        existing.join(", ")
+                    );
                 }
             }
         }
-        None
+        Ok(())
+    }
+
+    fn index_def(&mut self, idx: usize) {
+        match &self.defs[idx] {
+            ItemDef::Syscall(syscall_def) => {
+                self.syscalls_by_id.insert(syscall_def.id.0, idx);
+                self.syscalls_by_name.insert(syscall_def.name.clone(), idx);
+            }
+            def => {
+                self.types_by_name.insert(def.name().to_string(), idx);
+            }
+        }
+    }
+
+    fn unindex_def(&mut self, idx: usize) {
+        match &self.defs[idx] {
+            ItemDef::Syscall(syscall_def) => {
+                self.syscalls_by_id.remove(&syscall_def.id.0);
+                self.syscalls_by_name.remove(&syscall_def.name);
+            }
+            def => {
+                self.types_by_name.remove(def.name());
+            }
+        }
+    }
+
+    pub fn defs(&self) -> &[ItemDef] {
+        &self.defs
+    }
+
+    fn syscall_at(&self, idx: usize) -> &SyscallDef {
+        match &self.defs[idx] {
+            ItemDef::Syscall(syscall_def) => syscall_def,
+            _ => unreachable!("syscall index points to non-syscall definition"),
+        }
+    }
+
+    pub fn lookup_syscall_by_id(&self, id: SyscallId) -> Option<&SyscallDef> {
+        let idx = *self.syscalls_by_id.get(&id.0)?;
+        Some(self.syscall_at(idx))
+    }
+
+    pub fn lookup_syscall_by_name(&self, name: &str) -> Option<&SyscallDef> {
+        let idx = *self.syscalls_by_name.get(name)?;
+        Some(self.syscall_at(idx))
+    }
+
+    pub fn syscalls_in_group(&self, group: SyscallGroup) -> impl Iterator<Item = &SyscallDef> {
+        self.syscalls()
+            .filter(move |syscall_def| syscall_def.groups.contains(&group))
+    }
+
+    /// Resolves syscall name or group (e.g. `%file`) into syscall ids
+    pub fn resolve_syscall_set(&self, spec: &str) -> anyhow::Result<Vec<SyscallId>> {
+        match spec.strip_prefix('%') {
+            Some(group_name) => {
+                let group: SyscallGroup = group_name
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("unknown syscall group: {}", group_name))?;
+                Ok(self
+                    .syscalls_in_group(group)
+                    .map(|syscall_def| syscall_def.id.clone())
+                    .collect())
+            }
+            None => match self.lookup_syscall_by_name(spec) {
+                Some(syscall_def) => Ok(vec![syscall_def.id.clone()]),
+                None => anyhow::bail!("unknown syscall: {}", spec),
+            },
+        }
+    }
+
+    pub fn syscalls(&self) -> impl Iterator<Item = &SyscallDef> {
+        self.defs.iter().filter_map(|def| match def {
+            ItemDef::Syscall(syscall_def) => Some(syscall_def),
+            _ => None,
+        })
     }
 
     pub fn resolve_ty(&self, name: &str) -> &Ty {
@@ -29,24 +164,128 @@ impl Hir {
     }
 }
 
+#[derive(Clone)]
 pub enum ItemDef {
     Syscall(SyscallDef),
+    Struct(StructDef),
+    Flags(FlagsDef),
+    Enum(EnumDef),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+impl ItemDef {
+    pub fn name(&self) -> &str {
+        match self {
+            ItemDef::Syscall(def) => &def.name,
+            ItemDef::Struct(def) => &def.name,
+            ItemDef::Flags(def) => &def.name,
+            ItemDef::Enum(def) => &def.name,
+        }
+    }
+
+    pub fn loc(&self) -> &SourceLoc {
+        match self {
+            ItemDef::Syscall(def) => &def.loc,
+            ItemDef::Struct(def) => &def.loc,
+            ItemDef::Flags(def) => &def.loc,
+            ItemDef::Enum(def) => &def.loc,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            ItemDef::Syscall(def) => format!("syscall `{}` (id {})", def.name, def.id.0),
+            ItemDef::Struct(def) => format!("struct `{}`", def.name),
+            ItemDef::Flags(def) => format!("flags `{}`", def.name),
+            ItemDef::Enum(def) => format!("enum `{}`", def.name),
+        }
+    }
+}
+
+/// Position in magic file
+#[derive(Debug, Clone)]
+pub struct SourceLoc {
+    pub file: Arc<str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct SyscallId(pub u32);
 
-#[derive(Debug)]
+/// Syscall class, similar to strace's `%file`, `%network`, etc.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SyscallGroup {
+    /// Syscalls taking file name
+    File,
+    Network,
+    /// Process lifecycle
+    Process,
+    Memory,
+    Signal,
+    /// System V IPC
+    Ipc,
+    /// Syscalls taking or returning file descriptor
+    Desc,
+}
+
+impl SyscallGroup {
+    pub const ALL: &'static [SyscallGroup] = &[
+        SyscallGroup::File,
+        SyscallGroup::Network,
+        SyscallGroup::Process,
+        SyscallGroup::Memory,
+        SyscallGroup::Signal,
+        SyscallGroup::Ipc,
+        SyscallGroup::Desc,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SyscallGroup::File => "file",
+            SyscallGroup::Network => "network",
+            SyscallGroup::Process => "process",
+            SyscallGroup::Memory => "memory",
+            SyscallGroup::Signal => "signal",
+            SyscallGroup::Ipc => "ipc",
+            SyscallGroup::Desc => "desc",
+        }
+    }
+}
+
+impl std::str::FromStr for SyscallGroup {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<SyscallGroup, ()> {
+        SyscallGroup::ALL
+            .iter()
+            .copied()
+            .find(|group| group.name() == s)
+            .ok_or(())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct SyscallAnalyzeStrategy {
     pub on_enter: bool,
     pub on_exit: bool,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SyscallDef {
     pub id: SyscallId,
     pub name: String,
     pub body: DefBody,
     pub strategy: SyscallAnalyzeStrategy,
+    /// Set with `#[group = ...]` attributes
+    pub groups: Vec<SyscallGroup>,
+    /// Text of `///` comments
+    pub doc: Option<String>,
+    pub loc: SourceLoc,
 }
 
 impl SyscallDef {
@@ -73,9 +312,56 @@ impl SyscallDef {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct StructDef {
+    pub name: String,
+    pub body: DefBody,
+    /// Size, set with `#[size = ...]` attribute.
+    /// If `None`, size is calculated from fields layout.
+    pub size: Option<usize>,
+    /// Text of `///` comments
+    pub doc: Option<String>,
+    pub loc: SourceLoc,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlagsDef {
+    pub name: String,
+    pub flags: Vec<ConstDef>,
+    /// Size, set with `#[size = ...]` attribute.
+    pub size: Option<usize>,
+    /// Text of `///` comments
+    pub doc: Option<String>,
+    pub loc: SourceLoc,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<ConstDef>,
+    /// Size, set with `#[size = ...]` attribute.
+    pub size: Option<usize>,
+    /// Text of `///` comments
+    pub doc: Option<String>,
+    pub loc: SourceLoc,
+}
+
+/// Named constant, e.g. flag or enum variant
+#[derive(Debug, Clone)]
+pub struct ConstDef {
+    pub name: String,
+    pub value: u64,
+    /// Bits this constant is related to, set with `#[mask = ...]` attribute.
+    pub mask: Option<u64>,
+    /// Text of `///` comments
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone)]
 pub struct DefBody {
     pub fields: Vec<FieldDef>,
+    /// Indices of fields, ordered so that each field goes after fields it depends on
+    pub decode_order: Vec<usize>,
 }
 
 impl DefBody {
@@ -84,24 +370,59 @@ impl DefBody {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FieldTypeInfo {
     pub ty_name: String,
-    pub len_ref: Vec<String>,
+    /// Paths specified in `#[len = ...]` attribute, length is minimum of their values
+    pub len_ref: Vec<Vec<String>>,
+    /// Size in memory, overrides type's natural size (only for struct fields)
+    pub size: Option<usize>,
+    /// Path specified in `#[total_len = ...]` attribute (only for arrays): total length of
+    /// buffers in array elements. It is spread over them in order, e.g. `readv` fills
+    /// first buffer before second one
+    pub total_len_ref: Option<Vec<String>>,
 }
 
 impl FieldTypeInfo {
     pub fn deps(&self) -> Vec<String> {
-        let mut res = vec![];
-        if !self.len_ref.is_empty() {
-            res.push(self.len_ref[0].clone());
-        }
-        res
+        self.len_ref
+            .iter()
+            .chain(&self.total_len_ref)
+            .map(|path| path[0].clone())
+            .collect()
+    }
+}
+
+/// Specifies when field value is meaningful
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FieldDirection {
+    /// Passed to kernel, decoded on syscall entry
+    In,
+    /// Filled by kernel, decoded on syscall exit
+    Out,
+    /// Decoded both on entry and exit
+    InOut,
+}
+
+impl FieldDirection {
+    pub fn is_in(self) -> bool {
+        self != FieldDirection::Out
+    }
+
+    pub fn is_out(self) -> bool {
+        self != FieldDirection::In
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FieldDef {
     pub name: String,
     pub ty_info: FieldTypeInfo,
+    /// Set with `#[dir = ...]` attribute, only meaningful for syscall fields
+    pub dir: FieldDirection,
+    /// Offset in memory, overrides calculated one (only for struct fields)
+    pub offset: Option<usize>,
+    /// Text of `///` comments
+    pub doc: Option<String>,
+    pub loc: SourceLoc,
 }
@@ -1,39 +1,244 @@
 //! Performs parse tree -> hir conversion
 use crate::magic::{hir, parse_tree};
 use anyhow::Context;
+use std::sync::Arc;
 
-fn lower_def_body(ast: parse_tree::DefBody) -> hir::DefBody {
-    fn lower_field_def(ast: parse_tree::FieldDef) -> hir::FieldDef {
+/// Parses decimal or hexadecimal (with `0x` prefix) number
+fn parse_number(s: &str) -> anyhow::Result<u64> {
+    let num = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    };
+    Ok(num)
+}
+
+fn parse_number_attr(attrs: &parse_tree::Attrs, name: &str) -> anyhow::Result<Option<u64>> {
+    match attrs.get(name) {
+        Some(val) => {
+            let val = parse_number(val)
+                .with_context(|| format!("Failed to parse `{}` attribute", name))?;
+            Ok(Some(val))
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_size_attr(attrs: &parse_tree::Attrs, name: &str) -> anyhow::Result<Option<usize>> {
+    Ok(parse_number_attr(attrs, name)?.map(|size| size as usize))
+}
+
+fn make_loc(file: &Arc<str>, (line, column): (usize, usize)) -> hir::SourceLoc {
+    hir::SourceLoc {
+        file: file.clone(),
+        line,
+        column,
+    }
+}
+
+/// Attributes which can be specified several times
+const REPEATABLE_ATTRS: &[&str] = &["group"];
+
+/// Rejects unknown and repeated attributes
+fn check_attrs(file: &Arc<str>, attrs: &parse_tree::Attrs, allowed: &[&str]) -> anyhow::Result<()> {
+    let mut seen = Vec::new();
+    for attr in attrs.iter() {
+        let loc = make_loc(file, attr.line_col);
+        if !allowed.contains(&attr.name) {
+            anyhow::bail!(
+                "{}: unknown attribute `{}` (expected one of: {})",
+                loc,
+                attr.name,
+                allowed.join(", ")
+            );
+        }
+        if seen.contains(&attr.name) && !REPEATABLE_ATTRS.contains(&attr.name) {
+            anyhow::bail!("{}: attribute `{}` is specified twice", loc, attr.name);
+        }
+        seen.push(attr.name);
+    }
+    Ok(())
+}
+
+fn lower_const_def(file: &Arc<str>, ast: parse_tree::ConstDef) -> anyhow::Result<hir::ConstDef> {
+    let name = ast.name();
+    let attrs = ast.attrs();
+    let loc = make_loc(file, ast.line_col());
+    check_attrs(file, &attrs, &["mask"])?;
+    Ok(hir::ConstDef {
+        name: name.to_string(),
+        value: parse_number(ast.value())
+            .with_context(|| format!("{}: invalid value of constant `{}`", loc, name))?,
+        mask: parse_number_attr(&attrs, "mask")
+            .with_context(|| format!("{}: invalid constant `{}`", loc, name))?,
+        doc: ast.docs(),
+    })
+}
+
+fn lower_const_body(
+    file: &Arc<str>,
+    ast: parse_tree::ConstBody,
+) -> anyhow::Result<Vec<hir::ConstDef>> {
+    let mut consts: Vec<hir::ConstDef> = Vec::new();
+    for const_ast in ast.consts() {
+        let loc = make_loc(file, const_ast.line_col());
+        let const_def = lower_const_def(file, const_ast)?;
+        if consts.iter().any(|c| c.name == const_def.name) {
+            anyhow::bail!("{}: constant `{}` is defined twice", loc, const_def.name);
+        }
+        consts.push(const_def);
+    }
+    Ok(consts)
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum VisitState {
+    NotVisited,
+    InProgress,
+    Visited,
+}
+
+/// `path` contains fields being visited, it is used to report dependency cycle
+fn visit_field(
+    fields: &[hir::FieldDef],
+    idx: usize,
+    states: &mut [VisitState],
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> anyhow::Result<()> {
+    match states[idx] {
+        VisitState::Visited => return Ok(()),
+        VisitState::InProgress => {
+            let start = path.iter().position(|&i| i == idx).unwrap();
+            let cycle = path[start..]
+                .iter()
+                .chain(std::iter::once(&idx))
+                .map(|&i| fields[i].name.as_str())
+                .collect::<Vec<_>>();
+            anyhow::bail!(
+                "{}: dependency cycle: {}",
+                fields[idx].loc,
+                cycle.join(" -> ")
+            );
+        }
+        VisitState::NotVisited => {}
+    }
+    states[idx] = VisitState::InProgress;
+    path.push(idx);
+    for dep in fields[idx].ty_info.deps() {
+        let dep_idx = fields
+            .iter()
+            .position(|field| field.name == dep)
+            .with_context(|| {
+                format!(
+                    "{}: field `{}` refers to unknown field `{}`",
+                    fields[idx].loc, fields[idx].name, dep
+                )
+            })?;
+        visit_field(fields, dep_idx, states, path, order)?;
+    }
+    path.pop();
+    states[idx] = VisitState::Visited;
+    order.push(idx);
+    Ok(())
+}
+
+/// Topologically sorts fields by their dependencies.
+/// Independent fields keep definition order.
+fn decode_order(fields: &[hir::FieldDef]) -> anyhow::Result<Vec<usize>> {
+    let mut states = vec![VisitState::NotVisited; fields.len()];
+    let mut order = Vec::with_capacity(fields.len());
+    let mut path = Vec::new();
+    for idx in 0..fields.len() {
+        visit_field(fields, idx, &mut states, &mut path, &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Parses value of `len` attribute: either path or `min(path, ...)`
+fn parse_len_attr(val: &str) -> Vec<Vec<String>> {
+    let paths = match val.strip_prefix("min(") {
+        Some(args) => args.trim_end_matches(')').split(',').collect(),
+        None => vec![val],
+    };
+    paths
+        .into_iter()
+        .map(|path| path.trim().split("::").map(ToOwned::to_owned).collect())
+        .collect()
+}
+
+fn lower_def_body(file: &Arc<str>, ast: parse_tree::DefBody) -> anyhow::Result<hir::DefBody> {
+    fn lower_field_def(
+        ast: &parse_tree::FieldDef,
+        loc: hir::SourceLoc,
+    ) -> anyhow::Result<hir::FieldDef> {
         let name = ast.name();
         let ty = ast.ty();
         let attrs = ast.attrs();
+        check_attrs(
+            &loc.file,
+            &attrs,
+            &["len", "total_len", "size", "offset", "dir"],
+        )?;
         let ty_info = hir::FieldTypeInfo {
             ty_name: ty.to_string(),
-            len_ref: attrs
-                .get("len")
-                .map(|len| len.split("::").map(ToOwned::to_owned).collect())
-                .unwrap_or_else(Vec::new),
+            len_ref: attrs.get("len").map(parse_len_attr).unwrap_or_default(),
+            size: parse_size_attr(&attrs, "size")?,
+            total_len_ref: attrs
+                .get("total_len")
+                .map(|path| path.trim().split("::").map(ToOwned::to_owned).collect()),
+        };
+        let dir = match attrs.get("dir") {
+            Some("in") => hir::FieldDirection::In,
+            Some("out") => hir::FieldDirection::Out,
+            Some("inout") => hir::FieldDirection::InOut,
+            Some(s) => anyhow::bail!("unknown `dir` attr value: {}", s),
+            None if name == "ret" => hir::FieldDirection::Out,
+            None => hir::FieldDirection::In,
         };
-        hir::FieldDef {
+        Ok(hir::FieldDef {
             name: name.to_string(),
             ty_info,
+            dir,
+            offset: parse_size_attr(&attrs, "offset")?,
+            doc: ast.docs(),
+            loc,
+        })
+    }
+    let mut fields: Vec<hir::FieldDef> = Vec::new();
+    for field_ast in ast.fields() {
+        let loc = make_loc(file, field_ast.line_col());
+        let field = lower_field_def(&field_ast, loc.clone())
+            .with_context(|| format!("{}: invalid field `{}`", loc, field_ast.name()))?;
+        if fields.iter().any(|f| f.name == field.name) {
+            anyhow::bail!("{}: field `{}` is defined twice", loc, field.name);
         }
+        fields.push(field);
     }
-    let fields = ast.fields().map(lower_field_def).collect();
-    hir::DefBody { fields }
+    let decode_order = decode_order(&fields)?;
+    Ok(hir::DefBody {
+        fields,
+        decode_order,
+    })
 }
 
-fn lower_syscall_def(syscall_def_ast: parse_tree::SyscallDef) -> anyhow::Result<hir::SyscallDef> {
+/// Syscall arguments are passed in registers, see `RawSyscall::args`
+const MAX_SYSCALL_PARAMS: usize = 6;
+
+fn lower_syscall_def(
+    file: &Arc<str>,
+    syscall_def_ast: parse_tree::SyscallDef,
+) -> anyhow::Result<hir::SyscallDef> {
     let name = syscall_def_ast.name();
-    let mut attrs = syscall_def_ast.attrs();
-    let syscall_id = match attrs.remove("id") {
+    let attrs = syscall_def_ast.attrs();
+    check_attrs(file, &attrs, &["id", "kind", "group"])?;
+    let syscall_id = match attrs.get("id") {
         Some(id) => id.parse().context("Failed to parse `id` attribute")?,
         None => {
             anyhow::bail!("`id` attribute not set");
         }
     };
     let strategy = match attrs.get("kind") {
-        Some(s) => match *s {
+        Some(s) => match s {
             "in" => hir::SyscallAnalyzeStrategy {
                 on_enter: true,
                 on_exit: false,
@@ -53,35 +258,127 @@ fn lower_syscall_def(syscall_def_ast: parse_tree::SyscallDef) -> anyhow::Result<
             on_exit: true,
         },
     };
+    let groups = attrs
+        .get_all("group")
+        .map(|group| {
+            group
+                .parse()
+                .map_err(|_| anyhow::anyhow!("unknown `group` attr value: {}", group))
+        })
+        .collect::<anyhow::Result<Vec<hir::SyscallGroup>>>()?;
+    let body = lower_def_body(file, syscall_def_ast.def_body())?;
+    // decoder relies on return value being last field
+    match body.fields.iter().position(|field| field.name == "ret") {
+        Some(pos) if pos + 1 == body.fields.len() => {}
+        _ => anyhow::bail!("`ret` must be the last field"),
+    }
+    if let Some(extra) = body.fields[..body.fields.len() - 1].get(MAX_SYSCALL_PARAMS) {
+        anyhow::bail!(
+            "{}: field `{}`: syscall can not have more than {} parameters",
+            extra.loc,
+            extra.name,
+            MAX_SYSCALL_PARAMS
+        );
+    }
+    let ret = body.fields.last().unwrap();
+    if ret.dir != hir::FieldDirection::Out {
+        anyhow::bail!("{}: `ret` must have `out` direction", ret.loc);
+    }
+    for field in &body.fields {
+        if field.dir != hir::FieldDirection::In {
+            continue;
+        }
+        for dep in field.ty_info.deps() {
+            let dep_field = body.fields.iter().find(|f| f.name == dep).unwrap();
+            if dep_field.dir == hir::FieldDirection::Out {
+                anyhow::bail!(
+                    "{}: field `{}` is decoded on syscall entry, but depends on output field `{}`",
+                    field.loc,
+                    field.name,
+                    dep
+                );
+            }
+        }
+    }
     Ok(hir::SyscallDef {
         id: hir::SyscallId(syscall_id),
         name: name.to_string(),
-        body: lower_def_body(syscall_def_ast.def_body()),
+        body,
         strategy,
+        groups,
+        doc: syscall_def_ast.docs(),
+        loc: make_loc(file, syscall_def_ast.line_col()),
+    })
+}
+
+fn lower_struct_def(
+    file: &Arc<str>,
+    struct_def_ast: parse_tree::StructDef,
+) -> anyhow::Result<hir::StructDef> {
+    let attrs = struct_def_ast.attrs();
+    check_attrs(file, &attrs, &["size"])?;
+    Ok(hir::StructDef {
+        name: struct_def_ast.name().to_string(),
+        body: lower_def_body(file, struct_def_ast.def_body())?,
+        size: parse_size_attr(&attrs, "size")?,
+        doc: struct_def_ast.docs(),
+        loc: make_loc(file, struct_def_ast.line_col()),
     })
 }
 
-fn lower_def(def_ast: parse_tree::ItemDef) -> anyhow::Result<hir::ItemDef> {
+fn lower_flags_def(
+    file: &Arc<str>,
+    flags_def_ast: parse_tree::FlagsDef,
+) -> anyhow::Result<hir::FlagsDef> {
+    let attrs = flags_def_ast.attrs();
+    check_attrs(file, &attrs, &["size"])?;
+    Ok(hir::FlagsDef {
+        name: flags_def_ast.name().to_string(),
+        flags: lower_const_body(file, flags_def_ast.const_body())?,
+        size: parse_size_attr(&attrs, "size")?,
+        doc: flags_def_ast.docs(),
+        loc: make_loc(file, flags_def_ast.line_col()),
+    })
+}
+
+fn lower_enum_def(
+    file: &Arc<str>,
+    enum_def_ast: parse_tree::EnumDef,
+) -> anyhow::Result<hir::EnumDef> {
+    let attrs = enum_def_ast.attrs();
+    check_attrs(file, &attrs, &["size"])?;
+    Ok(hir::EnumDef {
+        name: enum_def_ast.name().to_string(),
+        variants: lower_const_body(file, enum_def_ast.const_body())?,
+        size: parse_size_attr(&attrs, "size")?,
+        doc: enum_def_ast.docs(),
+        loc: make_loc(file, enum_def_ast.line_col()),
+    })
+}
+
+fn lower_def(file: &Arc<str>, def_ast: parse_tree::ItemDef) -> anyhow::Result<hir::ItemDef> {
     let def = match def_ast {
-        parse_tree::ItemDef::Syscall(def) => hir::ItemDef::Syscall(lower_syscall_def(def)?),
+        parse_tree::ItemDef::Syscall(def) => hir::ItemDef::Syscall(lower_syscall_def(file, def)?),
+        parse_tree::ItemDef::Struct(def) => hir::ItemDef::Struct(lower_struct_def(file, def)?),
+        parse_tree::ItemDef::Flags(def) => hir::ItemDef::Flags(lower_flags_def(file, def)?),
+        parse_tree::ItemDef::Enum(def) => hir::ItemDef::Enum(lower_enum_def(file, def)?),
     };
     Ok(def)
 }
 
-pub(in crate::magic) fn lower(pt: parse_tree::Input) -> anyhow::Result<hir::Hir> {
-    Ok(hir::Hir {
-        defs: pt
-            .defs()
-            .map(|def| {
-                let def_name = def.name().to_string();
-                match lower_def(def) {
-                    ok @ Ok(_) => ok,
-                    Err(err) => {
-                        Err(err.context(format!("failed to lower definition `{}`", def_name)))
-                    }
-                }
-            })
-            .collect::<Result<Vec<_>, _>>()?,
-        types: hir::ty_db::TyDb::default(),
-    })
+/// Lowers all definitions from magic file, named `file` in diagnostics
+pub(crate) fn lower(pt: parse_tree::Input, file: &Arc<str>) -> anyhow::Result<Vec<hir::ItemDef>> {
+    pt.defs()
+        .map(|def| {
+            let def_name = def.name().to_string();
+            let loc = make_loc(file, def.line_col());
+            match lower_def(file, def) {
+                ok @ Ok(_) => ok,
+                Err(err) => Err(err.context(format!(
+                    "{}: failed to lower definition `{}`",
+                    loc, def_name
+                ))),
+            }
+        })
+        .collect()
 }
@@ -1,6 +1,325 @@
-//! Why so small? We don't support user-defined types yet (struct, union, flags, etc)
-use crate::magic::hir;
+//! Fills type database: primitive types and user-defined types
+use crate::magic::{
+    hir::{self, ty_db::TyDb},
+    ty::{ArrayTy, EnumTy, EnumVariant, Flag, FlagsTy, PrimitiveTy, StructField, StructTy, Ty},
+};
+use anyhow::Context;
 
-pub(in crate::magic) fn collect_types(hir: &mut hir::Hir) {
-    hir.types.insert_primitives();
+fn round_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Calculates struct layout, similar to C (i.e. respects alignment).
+/// Returns `None` if some field types are not known yet.
+fn layout_struct(types: &TyDb, def: &hir::StructDef) -> anyhow::Result<Option<StructTy>> {
+    let mut fields = Vec::new();
+    let mut end = 0;
+    let mut struct_align = 1;
+    for field in &def.body.fields {
+        let ty = match types.get(&field.ty_info.ty_name) {
+            Some(ty) => ty,
+            None => return Ok(None),
+        };
+        let (mut size, mut align) = ty
+            .layout()
+            .with_context(|| format!("field `{}` can not be stored in memory", field.name))?;
+        if let Some(explicit_size) = field.ty_info.size {
+            if matches!(ty, Ty::Struct(_)) || explicit_size == 0 || explicit_size > 8 {
+                anyhow::bail!("field `{}`: invalid `size` attribute", field.name);
+            }
+            size = explicit_size;
+            align = explicit_size.next_power_of_two();
+        }
+        let offset = field.offset.unwrap_or_else(|| round_up(end, align));
+        end = end.max(offset + size);
+        struct_align = struct_align.max(align);
+        fields.push(StructField {
+            name: field.name.clone(),
+            offset,
+            size,
+            ty_info: field.ty_info.clone(),
+        });
+    }
+    let size = match def.size {
+        Some(size) if size < end => {
+            anyhow::bail!("`size` is {}, but fields occupy {} bytes", size, end);
+        }
+        Some(size) => size,
+        None => round_up(end, struct_align),
+    };
+    Ok(Some(StructTy {
+        name: def.name.clone(),
+        size,
+        align: struct_align,
+        fields,
+        decode_order: def.body.decode_order.clone(),
+    }))
+}
+
+fn check_scalar_size(size: Option<usize>) -> anyhow::Result<usize> {
+    match size {
+        Some(size @ (1 | 2 | 4 | 8)) => Ok(size),
+        Some(size) => anyhow::bail!("invalid size {}", size),
+        None => Ok(4),
+    }
+}
+
+fn collect_flags(def: &hir::FlagsDef) -> anyhow::Result<FlagsTy> {
+    let flags = def
+        .flags
+        .iter()
+        .map(|flag| Flag {
+            name: flag.name.clone(),
+            value: flag.value,
+            mask: flag.mask.unwrap_or(flag.value),
+        })
+        .collect();
+    Ok(FlagsTy {
+        name: def.name.clone(),
+        size: check_scalar_size(def.size)?,
+        flags,
+    })
+}
+
+fn collect_enum(def: &hir::EnumDef) -> anyhow::Result<EnumTy> {
+    let variants = def
+        .variants
+        .iter()
+        .map(|variant| EnumVariant {
+            name: variant.name.clone(),
+            value: variant.value,
+        })
+        .collect();
+    Ok(EnumTy {
+        name: def.name.clone(),
+        size: check_scalar_size(def.size)?,
+        variants,
+    })
+}
+
+/// Splits `generic<arg>` into `generic` and `arg`
+fn parse_generic(ty_name: &str) -> Option<(&str, &str)> {
+    let arg_start = ty_name.find('<')?;
+    let arg = ty_name[arg_start + 1..].strip_suffix('>')?;
+    Some((&ty_name[..arg_start], arg))
+}
+
+/// Instantiates generic types (i.e. arrays), used by fields
+fn collect_generics(types: &mut TyDb, defs: &[&hir::ItemDef]) -> anyhow::Result<()> {
+    let mut instances = Vec::new();
+    for def in defs {
+        let body = match def {
+            hir::ItemDef::Syscall(syscall_def) => &syscall_def.body,
+            hir::ItemDef::Struct(struct_def) => &struct_def.body,
+            _ => continue,
+        };
+        for field in &body.fields {
+            let mut ty_name = field.ty_info.ty_name.as_str();
+            while let Some((generic, arg)) = parse_generic(ty_name) {
+                if generic != "array" {
+                    anyhow::bail!(
+                        "{}: field `{}`: unknown generic type {}",
+                        field.loc,
+                        field.name,
+                        generic
+                    );
+                }
+                instances.push((ty_name.to_string(), arg.to_string()));
+                ty_name = arg;
+            }
+        }
+    }
+    for (ty_name, elem_ty_name) in instances {
+        if types.get(&ty_name).is_none() {
+            types.add(&ty_name, Ty::Array(ArrayTy { elem_ty_name }));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that field type is known and `len` refers to numeric field
+fn check_field(
+    types: &TyDb,
+    field: &hir::FieldDef,
+    siblings: &[hir::FieldDef],
+) -> anyhow::Result<()> {
+    let ty = types.get(&field.ty_info.ty_name).with_context(|| {
+        format!(
+            "{}: field `{}` has unknown type `{}`",
+            field.loc, field.name, field.ty_info.ty_name
+        )
+    })?;
+    if let Ty::Array(array_ty) = ty {
+        let elem_ty = types.get(&array_ty.elem_ty_name).with_context(|| {
+            format!(
+                "{}: field `{}`: unknown array element type `{}`",
+                field.loc, field.name, array_ty.elem_ty_name
+            )
+        })?;
+        if elem_ty.layout().is_none() {
+            anyhow::bail!(
+                "{}: field `{}`: type `{}` can not be array element",
+                field.loc,
+                field.name,
+                array_ty.elem_ty_name
+            );
+        }
+    }
+    if field.ty_info.total_len_ref.is_some() && !matches!(ty, Ty::Array(_)) {
+        anyhow::bail!(
+            "{}: field `{}`: `total_len` can only be specified for arrays",
+            field.loc,
+            field.name
+        );
+    }
+    let len_refs = field.ty_info.len_ref.iter().map(|path| ("len", path));
+    let total_len_ref = field.ty_info.total_len_ref.iter();
+    for (attr, path) in len_refs.chain(total_len_ref.map(|path| ("total_len", path))) {
+        if path.len() != 1 {
+            // projection into struct field, checked when decoding
+            continue;
+        }
+        // unknown references were rejected during lowering
+        let len_field = siblings.iter().find(|f| f.name == path[0]).unwrap();
+        match types.get(&len_field.ty_info.ty_name) {
+            Some(Ty::Primitive(PrimitiveTy::Number)) => {}
+            _ => anyhow::bail!(
+                "{}: field `{}`: `{}` refers to field `{}` of type `{}`, but it must be `num`",
+                field.loc,
+                field.name,
+                attr,
+                len_field.name,
+                len_field.ty_info.ty_name
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn add_user_type(
+    types: &mut TyDb,
+    def_loc: &hir::SourceLoc,
+    name: &str,
+    ty: Ty,
+) -> anyhow::Result<()> {
+    if types.get(name).is_some() {
+        anyhow::bail!("{}: type `{}` is already defined", def_loc, name);
+    }
+    types.add(name, ty);
+    Ok(())
+}
+
+/// Adds types defined in layers starting from `first_layer` to type database, which must
+/// already contain types of previous layers. If `check_fields` is true, also checks fields
+/// of these layers (builtin magic is checked by build script, so it can skip that).
+pub(crate) fn collect_types(
+    hir: &mut hir::Hir,
+    first_layer: usize,
+    check_fields: bool,
+) -> anyhow::Result<()> {
+    if first_layer == 0 {
+        hir.types.insert_primitives();
+    }
+    let defs = hir
+        .defs
+        .iter()
+        .zip(&hir.def_layers)
+        .filter(|&(_, &layer)| layer >= first_layer)
+        .map(|(def, _)| def)
+        .collect::<Vec<_>>();
+    for def in &defs {
+        match def {
+            hir::ItemDef::Flags(flags_def) => {
+                let ty = collect_flags(flags_def).with_context(|| {
+                    format!("{}: invalid flags `{}`", flags_def.loc, flags_def.name)
+                })?;
+                add_user_type(
+                    &mut hir.types,
+                    &flags_def.loc,
+                    &flags_def.name,
+                    Ty::Flags(ty),
+                )?;
+            }
+            hir::ItemDef::Enum(enum_def) => {
+                let ty = collect_enum(enum_def).with_context(|| {
+                    format!("{}: invalid enum `{}`", enum_def.loc, enum_def.name)
+                })?;
+                add_user_type(&mut hir.types, &enum_def.loc, &enum_def.name, Ty::Enum(ty))?;
+            }
+            _ => {}
+        }
+    }
+    collect_generics(&mut hir.types, &defs)?;
+    let mut pending = defs
+        .iter()
+        .filter_map(|def| match def {
+            hir::ItemDef::Struct(struct_def) => Some(struct_def),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // structs can be embedded in each other, so we add them as soon as all field types are known
+    while !pending.is_empty() {
+        let mut next_pending = Vec::new();
+        for &struct_def in &pending {
+            let layout = layout_struct(&hir.types, struct_def).with_context(|| {
+                format!(
+                    "{}: failed to lay out struct `{}`",
+                    struct_def.loc, struct_def.name
+                )
+            })?;
+            match layout {
+                Some(ty) => add_user_type(
+                    &mut hir.types,
+                    &struct_def.loc,
+                    &struct_def.name,
+                    Ty::Struct(ty),
+                )?,
+                None => next_pending.push(struct_def),
+            }
+        }
+        if next_pending.len() == pending.len() {
+            // report unknown field types first, they are most likely cause
+            for struct_def in &pending {
+                for field in &struct_def.body.fields {
+                    let known = hir.types.get(&field.ty_info.ty_name).is_some()
+                        || pending.iter().any(|def| def.name == field.ty_info.ty_name);
+                    if !known {
+                        check_field(&hir.types, field, &struct_def.body.fields)?;
+                    }
+                }
+            }
+            let names = pending
+                .iter()
+                .map(|def| format!("`{}` ({})", def.name, def.loc))
+                .collect::<Vec<_>>();
+            anyhow::bail!("structs {} contain each other", names.join(", "));
+        }
+        pending = next_pending;
+    }
+    if !check_fields {
+        return Ok(());
+    }
+    for def in &defs {
+        let body = match def {
+            hir::ItemDef::Syscall(syscall_def) => &syscall_def.body,
+            hir::ItemDef::Struct(struct_def) => &struct_def.body,
+            _ => continue,
+        };
+        for field in &body.fields {
+            check_field(&hir.types, field, &body.fields)?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds types of layers, which were added to already collected first layer.
+/// If they override types of the first layer, all types are collected again, because
+/// first layer definitions may depend on them.
+pub(crate) fn collect_added_types(hir: &mut hir::Hir) -> anyhow::Result<()> {
+    if hir.first_layer_types_overridden {
+        hir.types = TyDb::default();
+        collect_types(hir, 0, true)
+    } else {
+        collect_types(hir, 1, true)
+    }
 }
@@ -2,7 +2,7 @@ use crate::magic::ty::{PrimitiveTy, Ty};
 use std::collections::HashMap;
 
 /// Type Database
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub(super) struct TyDb {
     data: HashMap<String, Ty>,
 }
@@ -14,6 +14,13 @@ impl TyDb {
         }
     }
 
+    pub(super) fn get(&self, name: &str) -> Option<&Ty> {
+        self.data.get(name)
+    }
+
+    /// Panics if type is unknown.
+    /// `collect_types` checks that all types used in magic are known, so
+    /// types of fields can always be looked up.
     pub(super) fn lookup(&self, name: &str) -> &Ty {
         self.data
             .get(name)
@@ -23,6 +30,7 @@ impl TyDb {
     pub(super) fn insert_primitives(&mut self) {
         self.add("fd", Ty::Primitive(PrimitiveTy::Fd));
         self.add("zstring", Ty::Primitive(PrimitiveTy::ZString));
+        self.add("zstring_array", Ty::Primitive(PrimitiveTy::ZStringArray));
         self.add("num", Ty::Primitive(PrimitiveTy::Number));
         self.add("buf", Ty::Primitive(PrimitiveTy::Buffer));
         self.add("signal", Ty::Primitive(PrimitiveTy::Signal));
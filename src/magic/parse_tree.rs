@@ -1,11 +1,10 @@
 //! Defines parse tree - thin typed wrapper for pest
 use super::raw::Rule;
-use std::collections::HashMap;
 
 // Utilities
 type N<'a> = pest::iterators::Pair<'a, Rule>;
 
-pub(super) fn new(p: pest::iterators::Pairs<Rule>) -> Input {
+pub(super) fn new(p: pest::iterators::Pairs<'_, Rule>) -> Input<'_> {
     let pairs = p.collect::<Vec<_>>();
     assert_eq!(pairs.len(), 1);
     Input::new(pairs.into_iter().next().unwrap())
@@ -19,7 +18,7 @@ macro_rules! define_node {
         impl<'a> $node_type<'a> {
             pub(super) const RULE: Rule = $rule;
 
-            pub(super) fn new(n: N<'a>) -> $node_type {
+            pub(super) fn new(n: N<'a>) -> $node_type<'a> {
                 assert_eq!(n.as_rule(), Self::RULE);
                 $node_type(n)
             }
@@ -32,36 +31,95 @@ macro_rules! define_node {
             fn child_at(&self, i: usize) -> N<'a> {
                 self.children().skip(i).next().unwrap()
             }
+
+            /// Returns line and column where node starts
+            #[allow(dead_code)]
+            pub(super) fn line_col(&self) -> (usize, usize) {
+                self.0.as_span().start_pos().line_col()
+            }
         }
     };
 }
 
-fn checked_node_text(n: N, rule: Rule) -> &str {
+fn checked_node_text(n: N<'_>, rule: Rule) -> &str {
     assert_eq!(n.as_rule(), rule);
     n.as_str()
 }
 
-pub(super) type Attrs<'a> = HashMap<&'a str, &'a str>;
+pub(super) struct Attr<'a> {
+    pub(super) name: &'a str,
+    pub(super) value: &'a str,
+    pub(super) line_col: (usize, usize),
+}
+
+/// Attributes in order of appearance
+pub(super) struct Attrs<'a>(Vec<Attr<'a>>);
+
+impl<'a> Attrs<'a> {
+    pub(super) fn get(&self, name: &str) -> Option<&'a str> {
+        self.0
+            .iter()
+            .find(|attr| attr.name == name)
+            .map(|attr| attr.value)
+    }
+
+    /// Returns values of all attributes with given name
+    pub(super) fn get_all<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'a str> + 's {
+        self.0
+            .iter()
+            .filter(move |attr| attr.name == name)
+            .map(|attr| attr.value)
+    }
 
-fn create_attrs(n: N) -> Attrs {
-    fn create_attr(n: N) -> (&str, &str) {
+    pub(super) fn iter(&self) -> impl Iterator<Item = &Attr<'a>> {
+        self.0.iter()
+    }
+}
+
+/// Joins `///` comments into single string, returns `None` if there are no comments
+fn create_docs(n: N<'_>) -> Option<String> {
+    assert_eq!(n.as_rule(), Rule::docs);
+    let lines = n
+        .into_inner()
+        .map(|line| {
+            let text = line.as_str().trim_start_matches("///");
+            text.strip_prefix(' ').unwrap_or(text).trim_end()
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+fn create_attrs(n: N<'_>) -> Attrs<'_> {
+    fn create_attr(n: N<'_>) -> Attr<'_> {
         assert_eq!(n.as_rule(), Rule::attr);
+        let line_col = n.as_span().start_pos().line_col();
         let mut iter = n.into_inner();
-        let k = iter.next().unwrap().as_str();
-        let v = iter.next().unwrap().as_str();
-        (k, v)
+        let name = iter.next().unwrap().as_str();
+        let value = iter.next().unwrap().as_str();
+        Attr {
+            name,
+            value,
+            line_col,
+        }
     }
     assert_eq!(n.as_rule(), Rule::attrs);
-    n.into_inner().map(create_attr).collect()
+    Attrs(n.into_inner().map(create_attr).collect())
 }
 
 // Nodes
 
 define_node!(Input, Rule::input);
 impl<'a> Input<'a> {
-    pub(super) fn defs(&self) -> impl Iterator<Item = ItemDef> {
+    pub(super) fn defs(&self) -> impl Iterator<Item = ItemDef<'a>> {
         self.children().filter_map(|child| match child.as_rule() {
             Rule::syscall_def => Some(ItemDef::Syscall(SyscallDef::new(child))),
+            Rule::struct_def => Some(ItemDef::Struct(StructDef::new(child))),
+            Rule::flags_def => Some(ItemDef::Flags(FlagsDef::new(child))),
+            Rule::enum_def => Some(ItemDef::Enum(EnumDef::new(child))),
             Rule::EOI => None,
             _ => unreachable!(),
         })
@@ -71,50 +129,150 @@ impl<'a> Input<'a> {
 define_node!(SyscallDef, Rule::syscall_def);
 
 impl<'a> SyscallDef<'a> {
-    pub(super) fn attrs(&self) -> Attrs {
-        create_attrs(self.child_at(0))
+    pub(super) fn docs(&self) -> Option<String> {
+        create_docs(self.child_at(0))
     }
-    pub(super) fn name(&self) -> &str {
-        checked_node_text(self.child_at(1), Rule::ident)
+    pub(super) fn attrs(&self) -> Attrs<'a> {
+        create_attrs(self.child_at(1))
     }
-    pub(super) fn def_body(&self) -> DefBody {
-        DefBody::new(self.child_at(2))
+    pub(super) fn name(&self) -> &'a str {
+        checked_node_text(self.child_at(2), Rule::ident)
+    }
+    pub(super) fn def_body(&self) -> DefBody<'a> {
+        DefBody::new(self.child_at(3))
+    }
+}
+
+define_node!(StructDef, Rule::struct_def);
+
+impl<'a> StructDef<'a> {
+    pub(super) fn docs(&self) -> Option<String> {
+        create_docs(self.child_at(0))
+    }
+    pub(super) fn attrs(&self) -> Attrs<'a> {
+        create_attrs(self.child_at(1))
+    }
+    pub(super) fn name(&self) -> &'a str {
+        checked_node_text(self.child_at(2), Rule::ident)
+    }
+    pub(super) fn def_body(&self) -> DefBody<'a> {
+        DefBody::new(self.child_at(3))
+    }
+}
+
+define_node!(FlagsDef, Rule::flags_def);
+
+impl<'a> FlagsDef<'a> {
+    pub(super) fn docs(&self) -> Option<String> {
+        create_docs(self.child_at(0))
+    }
+    pub(super) fn attrs(&self) -> Attrs<'a> {
+        create_attrs(self.child_at(1))
+    }
+    pub(super) fn name(&self) -> &'a str {
+        checked_node_text(self.child_at(2), Rule::ident)
+    }
+    pub(super) fn const_body(&self) -> ConstBody<'a> {
+        ConstBody::new(self.child_at(3))
+    }
+}
+
+define_node!(EnumDef, Rule::enum_def);
+
+impl<'a> EnumDef<'a> {
+    pub(super) fn docs(&self) -> Option<String> {
+        create_docs(self.child_at(0))
+    }
+    pub(super) fn attrs(&self) -> Attrs<'a> {
+        create_attrs(self.child_at(1))
+    }
+    pub(super) fn name(&self) -> &'a str {
+        checked_node_text(self.child_at(2), Rule::ident)
+    }
+    pub(super) fn const_body(&self) -> ConstBody<'a> {
+        ConstBody::new(self.child_at(3))
     }
 }
 
 define_node!(DefBody, Rule::def_body);
 
 impl<'a> DefBody<'a> {
-    pub(super) fn fields(&self) -> impl Iterator<Item = FieldDef> {
+    pub(super) fn fields(&self) -> impl Iterator<Item = FieldDef<'a>> {
         self.children().map(FieldDef::new)
     }
 }
 define_node!(FieldDef, Rule::field_def);
 
 impl<'a> FieldDef<'a> {
-    pub(super) fn attrs(&self) -> Attrs {
-        create_attrs(self.child_at(0))
+    pub(super) fn docs(&self) -> Option<String> {
+        create_docs(self.child_at(0))
+    }
+
+    pub(super) fn attrs(&self) -> Attrs<'a> {
+        create_attrs(self.child_at(1))
     }
 
     pub(super) fn name(&self) -> &'a str {
-        let node = self.child_at(1);
+        let node = self.child_at(2);
         checked_node_text(node, Rule::ident)
     }
 
     pub(super) fn ty(&self) -> &'a str {
-        let node = self.child_at(2);
-        checked_node_text(node, Rule::ident)
+        let node = self.child_at(3);
+        checked_node_text(node, Rule::ty_ref)
+    }
+}
+
+define_node!(ConstBody, Rule::const_body);
+
+impl<'a> ConstBody<'a> {
+    pub(super) fn consts(&self) -> impl Iterator<Item = ConstDef<'a>> {
+        self.children().map(ConstDef::new)
+    }
+}
+
+define_node!(ConstDef, Rule::const_def);
+
+impl<'a> ConstDef<'a> {
+    pub(super) fn docs(&self) -> Option<String> {
+        create_docs(self.child_at(0))
+    }
+    pub(super) fn attrs(&self) -> Attrs<'a> {
+        create_attrs(self.child_at(1))
+    }
+
+    pub(super) fn name(&self) -> &'a str {
+        checked_node_text(self.child_at(2), Rule::ident)
+    }
+
+    pub(super) fn value(&self) -> &'a str {
+        checked_node_text(self.child_at(3), Rule::number)
     }
 }
 
 pub(super) enum ItemDef<'a> {
     Syscall(SyscallDef<'a>),
+    Struct(StructDef<'a>),
+    Flags(FlagsDef<'a>),
+    Enum(EnumDef<'a>),
 }
 
 impl<'a> ItemDef<'a> {
-    pub(super) fn name(&self) -> &str {
+    pub(super) fn name(&self) -> &'a str {
         match self {
             ItemDef::Syscall(def) => def.name(),
+            ItemDef::Struct(def) => def.name(),
+            ItemDef::Flags(def) => def.name(),
+            ItemDef::Enum(def) => def.name(),
+        }
+    }
+
+    pub(super) fn line_col(&self) -> (usize, usize) {
+        match self {
+            ItemDef::Syscall(def) => def.line_col(),
+            ItemDef::Struct(def) => def.line_col(),
+            ItemDef::Flags(def) => def.line_col(),
+            ItemDef::Enum(def) => def.line_col(),
         }
     }
 }
@@ -1,3 +1,4 @@
+use super::hir::FieldTypeInfo;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
@@ -8,6 +9,8 @@ pub enum PrimitiveTy {
     Number,
     /// zero-terminated string
     ZString,
+    /// NULL-terminated array of pointers to zero-terminated strings
+    ZStringArray,
     /// Buffer
     Buffer,
     /// Signal ID
@@ -16,15 +19,141 @@ pub enum PrimitiveTy {
     Address,
 }
 
+impl PrimitiveTy {
+    /// Natural size in memory. For pointer-like types, size of pointer.
+    pub fn size(&self) -> usize {
+        match self {
+            PrimitiveTy::Fd | PrimitiveTy::Signal => 4,
+            PrimitiveTy::Number
+            | PrimitiveTy::ZString
+            | PrimitiveTy::ZStringArray
+            | PrimitiveTy::Buffer
+            | PrimitiveTy::Address => 8,
+        }
+    }
+
+    /// Whether value should be sign-extended when read from memory
+    pub fn is_signed(&self) -> bool {
+        matches!(self, PrimitiveTy::Number | PrimitiveTy::Signal)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StructField {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+    pub ty_info: FieldTypeInfo,
+}
+
+/// User-defined struct.
+/// When used as syscall argument, it is passed by pointer.
+/// When used as struct field, it is embedded.
+#[derive(Debug, Clone)]
+pub struct StructTy {
+    pub name: String,
+    pub size: usize,
+    pub align: usize,
+    pub fields: Vec<StructField>,
+    /// Indices of fields in order they should be decoded
+    pub decode_order: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Flag {
+    pub name: String,
+    pub value: u64,
+    /// Flag is set when `raw & mask == value`.
+    /// For simple one-bit flags `mask == value`.
+    pub mask: u64,
+}
+
+/// Set of named bit flags
+#[derive(Debug, Clone)]
+pub struct FlagsTy {
+    pub name: String,
+    pub size: usize,
+    pub flags: Vec<Flag>,
+}
+
+impl FlagsTy {
+    /// Splits `raw` into names of set flags and bits not covered by known flags.
+    /// Flags are matched in definition order, each bit is reported at most once.
+    pub fn split(&self, raw: u64) -> (Vec<String>, u64) {
+        let mut names = Vec::new();
+        let mut covered = 0;
+        for flag in &self.flags {
+            let is_set = if flag.mask == 0 {
+                raw == 0
+            } else {
+                raw & flag.mask == flag.value && covered & flag.mask != flag.mask
+            };
+            if is_set {
+                names.push(flag.name.clone());
+                covered |= flag.mask;
+            }
+        }
+        (names, raw & !covered)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    pub name: String,
+    pub value: u64,
+}
+
+/// Set of named constants, one of which is expected to be passed
+#[derive(Debug, Clone)]
+pub struct EnumTy {
+    pub name: String,
+    pub size: usize,
+    pub variants: Vec<EnumVariant>,
+}
+
+impl EnumTy {
+    pub fn variant_name(&self, raw: u64) -> Option<&str> {
+        self.variants
+            .iter()
+            .find(|variant| variant.value == raw)
+            .map(|variant| variant.name.as_str())
+    }
+}
+
+/// Pointer to array of elements of same type.
+/// Elements count is specified with `#[len = ...]` attribute.
+#[derive(Debug, Clone)]
+pub struct ArrayTy {
+    pub elem_ty_name: String,
+}
+
 /// Data type
 #[derive(Debug, Clone)]
 pub enum Ty {
     /// not used in this syscall
     Null,
     Primitive(PrimitiveTy),
+    Struct(StructTy),
+    Flags(FlagsTy),
+    Enum(EnumTy),
+    Array(ArrayTy),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Ty {
+    /// Returns size and alignment of value of this type, when it is stored in memory
+    pub fn layout(&self) -> Option<(usize, usize)> {
+        match self {
+            Ty::Null => None,
+            Ty::Primitive(prim) => Some((prim.size(), prim.size())),
+            Ty::Struct(st) => Some((st.size, st.align)),
+            Ty::Flags(flags) => Some((flags.size, flags.size)),
+            Ty::Enum(enum_ty) => Some((enum_ty.size, enum_ty.size)),
+            Ty::Array(_) => Some((8, 8)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "kind", content = "data")]
 #[serde(rename_all = "kebab-case")]
 // TODO: #[non_exhaustive]
@@ -33,10 +162,17 @@ pub enum Value {
     Handle(u32 /*raw fd value*/, Option<u64> /* ray id*/),
     String(String),
     Buffer(Box<[u8]>),
-    Flags(u64, Vec<String>),
+    Flags(
+        u64,         /*raw value*/
+        Vec<String>, /*set flags*/
+        u64,         /*unknown bits*/
+    ),
     Signal(i32, Option<String>),
+    Enum(i64 /*raw value*/, Option<String> /*variant name*/),
     Address(u64),
     Error(i32, String),
+    Struct(Vec<(String /*field name*/, Value)>),
+    Array(Vec<Value>, bool /*some elements were not read*/),
     Unknown,
     #[doc(hidden)]
     __NonExhaustive,
@@ -44,21 +180,23 @@ pub enum Value {
 
 impl Value {
     pub fn is_known(&self) -> bool {
-        match self {
-            Value::Unknown => false,
-            _ => true,
-        }
+        !matches!(self, Value::Unknown)
     }
 
     pub fn is_scalar(&self) -> bool {
-        true // revisit when implement structs
+        !matches!(self, Value::Struct(_) | Value::Array(..))
     }
 
-    pub fn project<'a>(
-        &'a self,
-        mut path: impl Iterator<Item = impl AsRef<str>>,
-    ) -> Option<&'a Value> {
+    pub fn project(&self, mut path: impl Iterator<Item = impl AsRef<str>>) -> Option<&Value> {
         match self {
+            Value::Struct(fields) => match path.next() {
+                Some(head) => {
+                    let head = head.as_ref();
+                    let field = fields.iter().find(|(name, _)| name == head)?;
+                    field.1.project(path)
+                }
+                None => Some(self),
+            },
             smth if smth.is_scalar() => {
                 if path.next().is_none() {
                     Some(self)
@@ -1,6 +1,9 @@
 use anyhow::Context;
 use lxtrace::{self, Event, EventPayload, Value};
-use std::{ffi::CString, io::Write, ops::Deref, path::PathBuf, process::exit};
+use std::{
+    ffi::CString, io::Write, ops::Deref, ops::RangeInclusive, path::PathBuf, process::exit,
+    time::Duration,
+};
 use structopt::StructOpt;
 
 fn print_data(arg: &Value, wr: &mut dyn Write) -> std::io::Result<()> {
@@ -16,21 +19,65 @@ fn print_data(arg: &Value, wr: &mut dyn Write) -> std::io::Result<()> {
             write!(wr, "\"{}\"", s)?;
         }
         Value::Buffer(buf) => {
-            if let Ok(s) = std::str::from_utf8(&buf) {
+            if let Ok(s) = std::str::from_utf8(buf) {
                 write!(wr, "\"{}\"", s)?;
             } else {
                 write!(wr, "<binary data>")?;
             }
         }
-        Value::Flags(_, _) => {
-            write!(wr, "TODO: flags")?;
+        Value::Flags(_, names, unknown) => {
+            for (i, name) in names.iter().enumerate() {
+                if i != 0 {
+                    write!(wr, "|")?;
+                }
+                write!(wr, "{}", name)?;
+            }
+            if *unknown != 0 {
+                if !names.is_empty() {
+                    write!(wr, "|")?;
+                }
+                write!(wr, "{:#x}", unknown)?;
+            } else if names.is_empty() {
+                write!(wr, "0")?;
+            }
         }
         Value::Signal(si_code, si_name) => match si_name {
             Some(name) => write!(wr, "{}", name)?,
             None => write!(wr, "signal #{}", si_code)?,
         },
+        Value::Enum(value, name) => match name {
+            Some(name) => write!(wr, "{}", name)?,
+            None => write!(wr, "{}", value)?,
+        },
         Value::Address(addr) => write!(wr, "{:p}", *addr as usize as *const ())?,
         Value::Error(code, name) => write!(wr, "error #{}: {}", code, name)?,
+        Value::Struct(fields) => {
+            write!(wr, "{{")?;
+            for (i, (name, value)) in fields.iter().enumerate() {
+                if i != 0 {
+                    write!(wr, ", ")?;
+                }
+                write!(wr, "{}=", name)?;
+                print_data(value, wr)?;
+            }
+            write!(wr, "}}")?;
+        }
+        Value::Array(elems, truncated) => {
+            write!(wr, "[")?;
+            for (i, elem) in elems.iter().enumerate() {
+                if i != 0 {
+                    write!(wr, ", ")?;
+                }
+                print_data(elem, wr)?;
+            }
+            if *truncated {
+                if !elems.is_empty() {
+                    write!(wr, ", ")?;
+                }
+                write!(wr, "...")?;
+            }
+            write!(wr, "]")?;
+        }
         Value::Unknown => {
             write!(wr, "<unknown>")?;
         }
@@ -39,21 +86,67 @@ fn print_data(arg: &Value, wr: &mut dyn Write) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Prints timestamp prefix, as requested by `-t` and `-r` options.
+/// `prev` is monotonic time of previous event.
+fn print_time(
+    time: &lxtrace::Timestamp,
+    opt: &Opt,
+    prev: &mut Option<Duration>,
+    wr: &mut dyn Write,
+) -> std::io::Result<()> {
+    let micros = time.wall.subsec_micros();
+    match opt.absolute_time {
+        0 => {}
+        1 | 2 => {
+            let secs = time.wall.as_secs() as libc::time_t;
+            let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+            unsafe { libc::localtime_r(&secs, &mut tm) };
+            write!(wr, "{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)?;
+            if opt.absolute_time == 2 {
+                write!(wr, ".{:06}", micros)?;
+            }
+            write!(wr, " ")?;
+        }
+        _ => write!(wr, "{}.{:06} ", time.wall.as_secs(), micros)?,
+    }
+    if opt.relative_time {
+        let delta = prev
+            .and_then(|prev| time.monotonic.checked_sub(prev))
+            .unwrap_or_default();
+        *prev = Some(time.monotonic);
+        write!(wr, "{:4}.{:06} ", delta.as_secs(), delta.subsec_micros())?;
+    }
+    Ok(())
+}
+
 #[derive(Copy, Clone)]
 enum SyscallEvent {
     Enter,
     Exit,
 }
 
-fn print_syscall_event(ev: Event, kind: SyscallEvent, wr: &mut dyn Write) -> std::io::Result<()> {
+fn print_syscall_event(
+    ev: Event,
+    kind: SyscallEvent,
+    show_duration: bool,
+    wr: &mut dyn Write,
+) -> std::io::Result<()> {
+    let (duration, injected) = match &ev.payload {
+        EventPayload::Sysexit {
+            duration, injected, ..
+        } => (*duration, *injected),
+        _ => (None, false),
+    };
     match ev.payload {
         EventPayload::Sysenter {
             raw: raw_data,
             decoded: data,
+            ..
         }
         | EventPayload::Sysexit {
             raw: raw_data,
             decoded: data,
+            ..
         } => {
             match &data {
                 Some(data) => {
@@ -94,12 +187,26 @@ fn print_syscall_event(ev: Event, kind: SyscallEvent, wr: &mut dyn Write) -> std
                     write!(wr, " = ")?;
 
                     match &data {
+                        _ if injected => {
+                            let errno = -(raw_data.ret as i64) as i32;
+                            write!(wr, "-1 {} (injected)", lxtrace::errno_name(errno))?;
+                        }
                         Some(dec) if dec.ret.as_ref().unwrap().is_known() => {
                             print_data(dec.ret.as_ref().unwrap(), wr)?;
-                            writeln!(wr,)?
                         }
-                        _ => writeln!(wr, "{}", raw_data.ret)?,
+                        _ => write!(wr, "{}", raw_data.ret)?,
                     }
+                    if show_duration {
+                        if let Some(duration) = duration {
+                            write!(
+                                wr,
+                                " <{}.{:06}>",
+                                duration.as_secs(),
+                                duration.subsec_micros()
+                            )?;
+                        }
+                    }
+                    writeln!(wr)?;
                 }
             }
             if let Some(data) = &data {
@@ -128,7 +235,7 @@ fn print_syscall_event(ev: Event, kind: SyscallEvent, wr: &mut dyn Write) -> std
     Ok(())
 }
 
-fn print_event(event: Event, wr: &mut dyn Write) -> std::io::Result<()> {
+fn print_event(event: Event, show_duration: bool, wr: &mut dyn Write) -> std::io::Result<()> {
     match event.payload {
         EventPayload::Attach => {
             writeln!(wr, "[{}]: attached", event.pid)?;
@@ -136,8 +243,48 @@ fn print_event(event: Event, wr: &mut dyn Write) -> std::io::Result<()> {
         EventPayload::Exit(exit_code) => {
             writeln!(wr, "[{}]: exited, code={}", event.pid, exit_code)?;
         }
-        EventPayload::Sysenter { .. } => print_syscall_event(event, SyscallEvent::Enter, wr)?,
-        EventPayload::Sysexit { .. } => print_syscall_event(event, SyscallEvent::Exit, wr)?,
+        EventPayload::Killed {
+            signal,
+            core_dumped,
+        } => {
+            let core_dumped = if core_dumped { " (core dumped)" } else { "" };
+            writeln!(
+                wr,
+                "[{}]: +++ killed by {}{} +++",
+                event.pid,
+                lxtrace::signal_name(signal),
+                core_dumped
+            )?;
+        }
+        EventPayload::Spawn {
+            parent,
+            child,
+            kind,
+        } => {
+            let kind = match kind {
+                lxtrace::SpawnKind::Fork => "fork",
+                lxtrace::SpawnKind::Vfork => "vfork",
+                lxtrace::SpawnKind::Clone => "clone",
+            };
+            writeln!(wr, "[{}]: spawned {} ({})", parent, child, kind)?;
+        }
+        EventPayload::Exec {
+            old_pid,
+            path,
+            argv,
+        } => {
+            write!(wr, "[{}]: executed {} {:?}", event.pid, path, argv)?;
+            if old_pid != event.pid {
+                write!(wr, " (by thread {})", old_pid)?;
+            }
+            writeln!(wr)?;
+        }
+        EventPayload::Sysenter { .. } => {
+            print_syscall_event(event, SyscallEvent::Enter, show_duration, wr)?
+        }
+        EventPayload::Sysexit { .. } => {
+            print_syscall_event(event, SyscallEvent::Exit, show_duration, wr)?
+        }
         EventPayload::Signal {
             raw: sig_code,
             decoded: sig_name,
@@ -153,6 +300,307 @@ fn print_event(event: Event, wr: &mut dyn Write) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Writes string as C literal, escaping special characters like strace does.
+/// If string is longer than `limit`, it is truncated and followed by `...`
+fn write_c_string(bytes: &[u8], limit: usize, wr: &mut dyn Write) -> std::io::Result<()> {
+    write!(wr, "\"")?;
+    for (i, &b) in bytes.iter().take(limit).enumerate() {
+        match b {
+            b'"' => write!(wr, "\\\"")?,
+            b'\\' => write!(wr, "\\\\")?,
+            b'\n' => write!(wr, "\\n")?,
+            b'\t' => write!(wr, "\\t")?,
+            b'\r' => write!(wr, "\\r")?,
+            0x0b => write!(wr, "\\v")?,
+            0x0c => write!(wr, "\\f")?,
+            0x20..=0x7e => write!(wr, "{}", b as char)?,
+            // octal escape must not absorb following digit
+            _ if bytes.get(i + 1).is_some_and(u8::is_ascii_digit) => write!(wr, "\\{:03o}", b)?,
+            _ => write!(wr, "\\{:o}", b)?,
+        }
+    }
+    write!(wr, "\"")?;
+    if bytes.len() > limit {
+        write!(wr, "...")?;
+    }
+    Ok(())
+}
+
+/// Prints events in strace format: one line per syscall, e.g. `read(3, "...", 4096) = 12`
+struct StraceFormatter<'a> {
+    opt: &'a Opt,
+    /// Syscall entries, which were not printed as complete lines yet
+    pending: std::collections::BTreeMap<u32, PendingSyscall>,
+    /// Number of running tracees, `[pid N]` prefix is printed if there are several
+    live_processes: usize,
+    prev_time: Option<Duration>,
+}
+
+struct PendingSyscall {
+    event: Event,
+    /// `<unfinished ...>` was printed, the rest will be printed as `<... resumed>`
+    unfinished_args: Option<usize>,
+}
+
+impl<'a> StraceFormatter<'a> {
+    fn new(opt: &'a Opt) -> StraceFormatter<'a> {
+        StraceFormatter {
+            opt,
+            pending: Default::default(),
+            live_processes: 0,
+            prev_time: None,
+        }
+    }
+
+    fn begin_line(
+        &mut self,
+        pid: u32,
+        time: &lxtrace::Timestamp,
+        wr: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        if self.live_processes > 1 {
+            write!(wr, "[pid {:5}] ", pid)?;
+        }
+        print_time(time, self.opt, &mut self.prev_time, wr)
+    }
+
+    fn print_value(&self, value: &Value, wr: &mut dyn Write) -> std::io::Result<()> {
+        let limit = self.opt.string_limit;
+        match value {
+            Value::Integral(num) => write!(wr, "{}", num),
+            Value::Handle(fd, _) => match *fd as i32 {
+                libc::AT_FDCWD => write!(wr, "AT_FDCWD"),
+                fd => write!(wr, "{}", fd),
+            },
+            Value::String(s) => write_c_string(s.as_bytes(), limit, wr),
+            Value::Buffer(buf) => write_c_string(buf, limit, wr),
+            Value::Flags(..) | Value::Signal(..) | Value::Enum(..) => print_data(value, wr),
+            Value::Address(0) => write!(wr, "NULL"),
+            Value::Address(addr) => write!(wr, "{:#x}", addr),
+            // strace prints `?` instead of -1, because syscall will be restarted
+            Value::Error(code @ (512 | 513 | 514 | 516), msg) => {
+                write!(wr, "? {} ({})", lxtrace::errno_name(*code), msg)
+            }
+            Value::Error(code, msg) => write!(wr, "-1 {} ({})", lxtrace::errno_name(*code), msg),
+            Value::Struct(fields) => {
+                write!(wr, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(wr, ", ")?;
+                    }
+                    write!(wr, "{}=", name)?;
+                    self.print_value(value, wr)?;
+                }
+                write!(wr, "}}")
+            }
+            Value::Array(elems, truncated) => {
+                write!(wr, "[")?;
+                self.print_args(elems, wr)?;
+                if *truncated {
+                    if !elems.is_empty() {
+                        write!(wr, ", ")?;
+                    }
+                    write!(wr, "...")?;
+                }
+                write!(wr, "]")
+            }
+            _ => write!(wr, "?"),
+        }
+    }
+
+    fn print_args(&self, args: &[Value], wr: &mut dyn Write) -> std::io::Result<()> {
+        for (i, arg) in args.iter().enumerate() {
+            if i != 0 {
+                write!(wr, ", ")?;
+            }
+            self.print_value(arg, wr)?;
+        }
+        Ok(())
+    }
+
+    /// Returns syscall name and arguments
+    fn syscall_args(payload: &EventPayload) -> (String, Vec<Value>) {
+        let (raw, decoded) = match payload {
+            EventPayload::Sysenter { raw, decoded, .. }
+            | EventPayload::Sysexit { raw, decoded, .. } => (raw, decoded),
+            _ => unreachable!(),
+        };
+        match decoded {
+            Some(syscall) => (syscall.name.clone(), syscall.args.clone()),
+            None => (
+                format!("syscall_{:#x}", raw.syscall_id),
+                raw.args.iter().map(|&arg| Value::Address(arg)).collect(),
+            ),
+        }
+    }
+
+    /// Prints rest of syscall line, starting from return value
+    fn finish_line(&self, exit: Option<&Event>, wr: &mut dyn Write) -> std::io::Result<()> {
+        write!(wr, ") = ")?;
+        match exit.map(|ev| &ev.payload) {
+            Some(EventPayload::Sysexit {
+                raw,
+                decoded,
+                duration,
+                injected,
+            }) => {
+                match decoded.as_ref().and_then(|syscall| syscall.ret.as_ref()) {
+                    _ if *injected => write!(
+                        wr,
+                        "-1 {} (injected)",
+                        lxtrace::errno_name(-(raw.ret as i64) as i32)
+                    )?,
+                    Some(ret) if ret.is_known() => self.print_value(ret, wr)?,
+                    _ => write!(wr, "{}", raw.ret as i64)?,
+                }
+                if let (true, Some(duration)) = (self.opt.syscall_time, duration) {
+                    write!(
+                        wr,
+                        " <{}.{:06}>",
+                        duration.as_secs(),
+                        duration.subsec_micros()
+                    )?;
+                }
+            }
+            _ => write!(wr, "?")?,
+        }
+        writeln!(wr)
+    }
+
+    /// Prints syscall line for pending entry, which will not get matching exit
+    fn flush_pending(&mut self, pid: u32, wr: &mut dyn Write) -> std::io::Result<()> {
+        if let Some(pending) = self.pending.remove(&pid) {
+            let (name, args) = Self::syscall_args(&pending.event.payload);
+            self.begin_line(pid, &pending.event.time, wr)?;
+            match pending.unfinished_args {
+                Some(_) => write!(wr, "<... {} resumed>", name)?,
+                None => {
+                    write!(wr, "{}(", name)?;
+                    self.print_args(&args, wr)?;
+                }
+            }
+            self.finish_line(None, wr)?;
+        }
+        Ok(())
+    }
+
+    /// Prints `<unfinished ...>` for syscalls of other threads, which are still in progress
+    fn interrupt_pending(&mut self, pid: u32, wr: &mut dyn Write) -> std::io::Result<()> {
+        let others = self
+            .pending
+            .iter()
+            .filter(|(&other, pending)| other != pid && pending.unfinished_args.is_none())
+            .map(|(&other, _)| other)
+            .collect::<Vec<_>>();
+        for other in others {
+            let (name, args) = Self::syscall_args(&self.pending[&other].event.payload);
+            // output arguments are not known yet
+            let known = args.iter().take_while(|arg| arg.is_known()).count();
+            let time = self.pending[&other].event.time;
+            self.begin_line(other, &time, wr)?;
+            write!(wr, "{}(", name)?;
+            self.print_args(&args[..known], wr)?;
+            if known != 0 && known != args.len() {
+                write!(wr, ", ")?;
+            }
+            writeln!(wr, " <unfinished ...>")?;
+            self.pending.get_mut(&other).unwrap().unfinished_args = Some(known);
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, event: Event, wr: &mut dyn Write) -> std::io::Result<()> {
+        let pid = event.pid;
+        match &event.payload {
+            EventPayload::Attach => {
+                self.live_processes += 1;
+                return Ok(());
+            }
+            EventPayload::Spawn { .. } => return Ok(()),
+            _ => {}
+        }
+        self.interrupt_pending(pid, wr)?;
+        match &event.payload {
+            EventPayload::Sysenter { .. } => {
+                // previous syscall did not return (e.g. it was interrupted)
+                self.flush_pending(pid, wr)?;
+                self.pending.insert(
+                    pid,
+                    PendingSyscall {
+                        event,
+                        unfinished_args: None,
+                    },
+                );
+            }
+            EventPayload::Sysexit { .. } => {
+                let (name, args) = Self::syscall_args(&event.payload);
+                let pending = self.pending.remove(&pid);
+                match pending.as_ref().and_then(|pending| pending.unfinished_args) {
+                    Some(printed) => {
+                        self.begin_line(pid, &event.time, wr)?;
+                        write!(wr, "<... {} resumed>", name)?;
+                        if printed < args.len() {
+                            self.print_args(&args[printed..], wr)?;
+                        }
+                    }
+                    None => {
+                        let time = pending
+                            .as_ref()
+                            .map_or(event.time, |pending| pending.event.time);
+                        self.begin_line(pid, &time, wr)?;
+                        write!(wr, "{}(", name)?;
+                        self.print_args(&args, wr)?;
+                    }
+                }
+                self.finish_line(Some(&event), wr)?;
+            }
+            EventPayload::Signal { decoded, .. } => {
+                self.begin_line(pid, &event.time, wr)?;
+                writeln!(wr, "--- {} ---", decoded)?;
+            }
+            EventPayload::Exec { old_pid, .. } if *old_pid != pid => {
+                // thread which called execve took pid of thread group leader
+                if let Some(pending) = self.pending.remove(old_pid) {
+                    self.pending.insert(pid, pending);
+                }
+                self.live_processes = self.live_processes.saturating_sub(1);
+            }
+            EventPayload::Exit(code) => {
+                self.flush_pending(pid, wr)?;
+                self.begin_line(pid, &event.time, wr)?;
+                writeln!(wr, "+++ exited with {} +++", code)?;
+                self.live_processes = self.live_processes.saturating_sub(1);
+            }
+            EventPayload::Killed {
+                signal,
+                core_dumped,
+            } => {
+                self.flush_pending(pid, wr)?;
+                self.begin_line(pid, &event.time, wr)?;
+                let core_dumped = if *core_dumped { " (core dumped)" } else { "" };
+                writeln!(
+                    wr,
+                    "+++ killed by {}{} +++",
+                    lxtrace::signal_name(*signal),
+                    core_dumped
+                )?;
+                self.live_processes = self.live_processes.saturating_sub(1);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Prints syscalls which are still in progress
+    fn finish(&mut self, wr: &mut dyn Write) -> std::io::Result<()> {
+        let pids = self.pending.keys().copied().collect::<Vec<_>>();
+        for pid in pids {
+            self.flush_pending(pid, wr)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 struct XCString(CString);
 
@@ -171,6 +619,157 @@ impl std::ops::Deref for XCString {
     }
 }
 
+/// Syscall numbers used on x86_64 (as of Linux 6.10)
+const X86_64_SYSCALL_IDS: &[RangeInclusive<u32>] = &[0..=334, 424..=462];
+
+/// Loads builtin magic and `files`, printing syscall coverage.
+/// Returns false if magic can't be loaded or contains syscalls outside of x86_64 range.
+/// If `strict` is set, syscalls with only `ret` field are errors too
+fn check_magic(files: &[PathBuf], strict: bool) -> bool {
+    let magic = match lxtrace::magic::init_from_files(files) {
+        Ok(magic) => magic,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            return false;
+        }
+    };
+    let mut syscalls = magic.syscalls().collect::<Vec<_>>();
+    syscalls.sort_by_key(|def| def.id.0);
+    let (full, ret_only): (Vec<_>, Vec<_>) =
+        syscalls.iter().partition(|def| def.params_count() != 0);
+    let is_x86_64_id = |id: u32| X86_64_SYSCALL_IDS.iter().any(|range| range.contains(&id));
+    let missing = X86_64_SYSCALL_IDS
+        .iter()
+        .flat_map(|range| range.clone())
+        .filter(|&id| !syscalls.iter().any(|def| def.id.0 == id))
+        .collect::<Vec<_>>();
+    let foreign = syscalls
+        .iter()
+        .filter(|def| !is_x86_64_id(def.id.0))
+        .collect::<Vec<_>>();
+
+    println!("{:<28} {:>5}", "syscalls", syscalls.len());
+    println!("{:<28} {:>5}", "  with full signature", full.len());
+    println!("{:<28} {:>5}", "  with only `ret`", ret_only.len());
+    println!("{:<28} {:>5}", "  outside of x86_64 range", foreign.len());
+    println!("{:<28} {:>5}", "missing x86_64 syscalls", missing.len());
+    let describe = |def: &&&lxtrace::magic::hir::SyscallDef| format!("{} ({})", def.name, def.id.0);
+    if !ret_only.is_empty() {
+        let names = ret_only.iter().map(describe).collect::<Vec<_>>();
+        println!("\nonly `ret`: {}", names.join(", "));
+    }
+    if !foreign.is_empty() {
+        let names = foreign.iter().map(describe).collect::<Vec<_>>();
+        println!("\noutside of x86_64 range: {}", names.join(", "));
+    }
+    if !missing.is_empty() {
+        let ids = missing.iter().map(ToString::to_string).collect::<Vec<_>>();
+        println!("\nmissing ids: {}", ids.join(", "));
+    }
+    let mut ok = true;
+    if !foreign.is_empty() {
+        eprintln!("error: {} syscalls outside of x86_64 range", foreign.len());
+        ok = false;
+    }
+    if strict && !ret_only.is_empty() {
+        eprintln!("error: {} syscalls with only `ret`", ret_only.len());
+        ok = false;
+    }
+    ok
+}
+
+fn print_doc(doc: &Option<String>, indent: &str) {
+    if let Some(doc) = doc {
+        for line in doc.lines() {
+            println!("{}{}", indent, line);
+        }
+    }
+}
+
+/// Prints signature and documentation of syscall, specified by name or id.
+/// Returns false if syscall is unknown
+fn describe_syscall(magic: &lxtrace::magic::Magic, syscall: &str) -> bool {
+    use lxtrace::magic::hir::FieldDirection;
+    let def = match syscall.parse() {
+        Ok(id) => magic.lookup_syscall_by_id(lxtrace::magic::hir::SyscallId(id)),
+        Err(_) => magic.lookup_syscall_by_name(syscall),
+    };
+    let def = match def {
+        Some(def) => def,
+        None => {
+            eprintln!("unknown syscall: {}", syscall);
+            return false;
+        }
+    };
+    let params = def
+        .params()
+        .map(|(_, field)| format!("{}: {}", field.name, field.ty_info.ty_name))
+        .collect::<Vec<_>>();
+    println!(
+        "syscall {}({}) -> {} (id {})",
+        def.name,
+        params.join(", "),
+        def.ret().ty_info.ty_name,
+        def.id.0
+    );
+    print_doc(&def.doc, "    ");
+    if !def.groups.is_empty() {
+        let groups = def
+            .groups
+            .iter()
+            .map(|group| format!("%{}", group.name()))
+            .collect::<Vec<_>>();
+        println!("    groups: {}", groups.join(", "));
+    }
+    for field in &def.body.fields {
+        let dir = match field.dir {
+            FieldDirection::In => "in",
+            FieldDirection::Out => "out",
+            FieldDirection::InOut => "inout",
+        };
+        let mut line = format!("  {}: {} [{}]", field.name, field.ty_info.ty_name, dir);
+        if !field.ty_info.len_ref.is_empty() {
+            let paths = field
+                .ty_info
+                .len_ref
+                .iter()
+                .map(|path| path.join("::"))
+                .collect::<Vec<_>>();
+            if paths.len() == 1 {
+                line += &format!(" len = {}", paths[0]);
+            } else {
+                line += &format!(" len = min({})", paths.join(", "));
+            }
+        }
+        if let Some(path) = &field.ty_info.total_len_ref {
+            line += &format!(" total_len = {}", path.join("::"));
+        }
+        println!("{}", line);
+        print_doc(&field.doc, "      ");
+    }
+    true
+}
+
+#[derive(StructOpt, Clone)]
+enum MagicCommand {
+    /// Validates magic files (in addition to builtin magic) and prints syscall coverage.
+    /// Fails if some syscalls are outside of x86_64 range
+    Check {
+        /// Also fail if some syscalls have only `ret` field
+        #[structopt(long)]
+        strict: bool,
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(StructOpt, Clone)]
+enum Command {
+    /// Magic files tools
+    Magic(MagicCommand),
+    /// Prints signature and documentation of syscall (specified by name or number)
+    Describe { syscall: String },
+}
+
 #[derive(StructOpt, Clone)]
 struct Opt {
     #[structopt(last = true)]
@@ -187,13 +786,99 @@ struct Opt {
     /// Child will inherit all environment vars visible to lxtrace
     #[structopt(long)]
     inherit_env: bool,
+    /// Load additional syscall definitions from file (can be specified several times).
+    /// They override builtin definitions with the same syscall id or name
+    #[structopt(long = "magic", number_of_values = 1)]
+    magic_files: Vec<PathBuf>,
+    /// Trace only specified syscalls: comma-separated list of names, groups (`%file`)
+    /// and regexes (`/^rt_`). Leading `!` inverts the list (can be specified several times)
+    #[structopt(long, number_of_values = 1)]
+    trace: Vec<String>,
+    /// Make syscalls fail without executing them, e.g. `open,openat:error=ENOENT:when=3+`.
+    /// `when` selects invocations (counted for each process and syscall): `N`, `N+`
+    /// (N-th and later) or `N+S` (every S-th starting from N-th). Can be specified several times
+    #[structopt(long, number_of_values = 1)]
+    inject: Vec<String>,
+    /// Print time of day for each event. Repeat (`-tt`) to print microseconds,
+    /// or `-ttt` to print UNIX time
+    #[structopt(short = "t", parse(from_occurrences))]
+    absolute_time: u8,
+    /// Print time elapsed since previous event
+    #[structopt(short = "r")]
+    relative_time: bool,
+    /// Print time spent in each syscall
+    #[structopt(short = "T")]
+    syscall_time: bool,
+    /// Print trace in strace format, one line per syscall
+    #[structopt(long)]
+    strace: bool,
+    /// Maximum length of printed strings in strace format
+    #[structopt(long, short = "s", default_value = "32")]
+    string_limit: usize,
+    /// Print syscall statistics table at exit instead of trace
+    #[structopt(long, short = "c")]
+    summary: bool,
+    /// Print syscall statistics table at exit in addition to trace
+    #[structopt(long, short = "C")]
+    summary_with_trace: bool,
+    /// Kill tracees when lxtrace is interrupted. By default, on SIGINT or SIGTERM lxtrace
+    /// detaches from them and they continue running
+    #[structopt(long)]
+    kill_on_exit: bool,
+    /// Run tracee under seccomp filter, which stops it only on syscalls selected by `--trace`
+    /// and `--inject`. This is much faster, but tracee is always killed when lxtrace is
+    /// interrupted, and may get `no_new_privs` flag (setuid executables lose privileges).
+    /// Implied by `--kill-on-exit`
+    #[structopt(long)]
+    seccomp: bool,
+    /// Attach to already running process (can be specified several times)
+    #[structopt(long = "pid", short = "p", number_of_values = 1)]
+    pids: Vec<u32>,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+fn make_settings(opt: &Opt) -> lxtrace::Settings {
+    lxtrace::Settings {
+        capture_backtrace: opt.backtrace,
+        magic_files: opt.magic_files.clone(),
+        trace: opt.trace.clone(),
+        kill_on_exit: opt.kill_on_exit,
+        inject: opt.inject.clone(),
+        seccomp: opt.seccomp,
+        // strace formatter prints syscall on entry if it is interrupted by other tracee,
+        // summary and `-T` need exits of all syscalls, including `#[kind = in]` ones
+        report_all_stops: (opt.strace && !opt.json)
+            || opt.summary
+            || opt.summary_with_trace
+            || opt.syscall_time,
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     use std::os::unix::ffi::OsStringExt;
     let mut opt: Opt = Opt::from_args();
-    if opt.args.is_empty() {
-        eprintln!("executable not provided");
+    if let Some(Command::Magic(MagicCommand::Check { strict, files })) = &opt.cmd {
+        let files = opt
+            .magic_files
+            .iter()
+            .chain(files)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !check_magic(&files, *strict) {
+            exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Command::Describe { syscall }) = &opt.cmd {
+        let magic = lxtrace::magic::init_from_files(&opt.magic_files)?;
+        if !describe_syscall(&magic, syscall) {
+            exit(1);
+        }
+        return Ok(());
+    }
+    if opt.args.is_empty() && opt.pids.is_empty() {
+        eprintln!("neither executable nor pid provided");
         exit(1);
     }
     if opt.inherit_env {
@@ -208,10 +893,37 @@ fn main() -> anyhow::Result<()> {
             opt.env.push(XCString(cs));
         }
     }
-    let (sender, receiver) = crossbeam::channel::unbounded();
     {
+        // signals are handled in dedicated thread, tracers are asked to stop.
+        // Signal mask is inherited by other threads, so it must be set before spawning them
+        let mut signals = nix::sys::signal::SigSet::empty();
+        signals.add(nix::sys::signal::Signal::SIGINT);
+        signals.add(nix::sys::signal::Signal::SIGTERM);
+        signals
+            .thread_block()
+            .context("failed to block termination signals")?;
+        std::thread::spawn(move || loop {
+            if signals.wait().is_ok() {
+                lxtrace::stop_all();
+            }
+        });
+    }
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    let mut tracer_threads = Vec::new();
+    // each traced process gets its own tracer, they all report to the same channel
+    for &pid in &opt.pids {
         let opt = opt.clone();
-        unsafe {
+        let sender = sender.clone();
+        tracer_threads.push(unsafe {
+            std::thread::spawn(move || {
+                lxtrace::run(lxtrace::Payload::Attach(pid), make_settings(&opt), sender)
+            })
+        });
+    }
+    if !opt.args.is_empty() {
+        let opt = opt.clone();
+        let sender = sender.clone();
+        tracer_threads.push(unsafe {
             // we spawn new thread, because lxtrace will block it until child finishes
             std::thread::spawn(move || {
                 let arg0 = opt.args[0].clone();
@@ -231,16 +943,11 @@ fn main() -> anyhow::Result<()> {
                         .collect::<Vec<_>>(),
                 };
                 let payload = lxtrace::Payload::Cmd(cmd_args);
-                let settings = lxtrace::Settings {
-                    capture_backtrace: opt.backtrace,
-                };
-
-                if let Err(e) = lxtrace::run(payload, settings, sender) {
-                    eprintln!("{:?}", e);
-                }
-            });
-        }
+                lxtrace::run(payload, make_settings(&opt), sender)
+            })
+        });
     }
+    drop(sender);
     let mut out: Box<dyn std::io::Write> = match &opt.file {
         Some(path) => {
             let file = std::fs::File::create(path).context("failed to open log file")?;
@@ -248,19 +955,60 @@ fn main() -> anyhow::Result<()> {
         }
         None => Box::new(std::io::stdout()),
     };
-    loop {
-        let event = match receiver.recv() {
-            Ok(x) => x,
-            Err(_) => {
-                break;
+    let mut prev_time = None;
+    let mut strace_formatter = if opt.strace && !opt.json {
+        Some(StraceFormatter::new(&opt))
+    } else {
+        None
+    };
+    let mut summary = if opt.summary || opt.summary_with_trace {
+        Some(lxtrace::summary::Summary::new())
+    } else {
+        None
+    };
+    while let Ok(event) = receiver.recv() {
+        if let Some(summary) = &mut summary {
+            summary.process(&event);
+            if !opt.summary_with_trace {
+                continue;
             }
-        };
+        }
         if opt.json {
             let s = serde_json::to_string(&event).expect("failed to serialize");
             writeln!(&mut *out, "{}", s)?;
+        } else if let Some(formatter) = &mut strace_formatter {
+            formatter.process(event, &mut *out).ok();
         } else {
-            print_event(event, &mut *out).ok();
+            print_time(&event.time, &opt, &mut prev_time, &mut *out).ok();
+            print_event(event, opt.syscall_time, &mut *out).ok();
         }
     }
+    if let Some(formatter) = &mut strace_formatter {
+        formatter.finish(&mut *out).ok();
+    }
+    if let Some(summary) = &summary {
+        if opt.json {
+            let s = serde_json::to_string(summary).expect("failed to serialize");
+            writeln!(&mut *out, "{}", s)?;
+        } else {
+            summary.write_table(&mut *out)?;
+        }
+    }
+    // `exit` below does not run destructors
+    match out.flush() {
+        // output reader (e.g. `head`) has gone, this is not an error
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {}
+        res => res.context("failed to flush output")?,
+    }
+    let mut failed = false;
+    for tracer_thread in tracer_threads {
+        if let Err(e) = tracer_thread.join().expect("tracer thread panicked") {
+            eprintln!("{:?}", e);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
     Ok(())
 }
@@ -0,0 +1,194 @@
+//! Syscall statistics, collected from event stream
+use crate::{errno_name, Event, EventPayload, RawSyscall, Syscall};
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write, time::Duration};
+
+/// Statistics of one syscall
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SyscallStats {
+    pub calls: u64,
+    /// Number of failed calls for each errno
+    pub errors: BTreeMap<i32, u64>,
+    /// Total time spent in syscall. Only calls with known duration are taken into account
+    pub total_time: Duration,
+    pub max_time: Duration,
+    /// Number of calls with known duration
+    pub timed_calls: u64,
+}
+
+impl SyscallStats {
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    pub fn avg_time(&self) -> Duration {
+        if self.timed_calls == 0 {
+            return Duration::default();
+        }
+        self.total_time / self.timed_calls as u32
+    }
+
+    fn merge(&mut self, other: &SyscallStats) {
+        self.calls += other.calls;
+        for (&errno, &count) in &other.errors {
+            *self.errors.entry(errno).or_default() += count;
+        }
+        self.total_time += other.total_time;
+        self.max_time = self.max_time.max(other.max_time);
+        self.timed_calls += other.timed_calls;
+    }
+}
+
+/// Aggregates per-syscall statistics from events produced by `run`.
+///
+/// Depending on magic, syscall can be reported on entry, on exit or both, so
+/// call is counted once for entry and following exit of the same thread.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    /// Statistics for each process (thread group) and syscall name
+    processes: BTreeMap<u32, BTreeMap<String, SyscallStats>>,
+    /// Syscall id of counted sysenter event, for which sysexit was not seen yet
+    #[serde(skip)]
+    pending: BTreeMap<u32, u64>,
+}
+
+fn syscall_name(raw: &RawSyscall, decoded: &Option<Syscall>) -> String {
+    match decoded {
+        Some(syscall) => syscall.name.clone(),
+        None => format!("syscall_{}", raw.syscall_id),
+    }
+}
+
+impl Summary {
+    pub fn new() -> Summary {
+        Summary::default()
+    }
+
+    pub fn process(&mut self, event: &Event) {
+        match &event.payload {
+            EventPayload::Sysenter { raw, decoded, .. } => {
+                let stats = self.stats_mut(event.tgid, syscall_name(raw, decoded));
+                stats.calls += 1;
+                self.pending.insert(event.pid, raw.syscall_id);
+            }
+            EventPayload::Sysexit {
+                raw,
+                decoded,
+                duration,
+                ..
+            } => {
+                let counted = self.pending.remove(&event.pid) == Some(raw.syscall_id);
+                let stats = self.stats_mut(event.tgid, syscall_name(raw, decoded));
+                if !counted {
+                    stats.calls += 1;
+                }
+                let ret = raw.ret as i64;
+                if (-4095..0).contains(&ret) {
+                    *stats.errors.entry(-ret as i32).or_default() += 1;
+                }
+                if let Some(duration) = *duration {
+                    stats.total_time += duration;
+                    stats.max_time = stats.max_time.max(duration);
+                    stats.timed_calls += 1;
+                }
+            }
+            EventPayload::Exec { old_pid, .. } => {
+                // execve returns in thread group leader, even if other thread called it
+                if let Some(syscall_id) = self.pending.remove(old_pid) {
+                    self.pending.insert(event.pid, syscall_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn stats_mut(&mut self, tgid: u32, name: String) -> &mut SyscallStats {
+        self.processes
+            .entry(tgid)
+            .or_default()
+            .entry(name)
+            .or_default()
+    }
+
+    /// Returns statistics for each syscall, summed over all processes
+    pub fn syscalls(&self) -> BTreeMap<String, SyscallStats> {
+        let mut total = BTreeMap::<String, SyscallStats>::new();
+        for syscalls in self.processes.values() {
+            for (name, stats) in syscalls {
+                total.entry(name.clone()).or_default().merge(stats);
+            }
+        }
+        total
+    }
+
+    /// Returns statistics for each process and syscall. Threads of process are counted together
+    pub fn processes(&self) -> &BTreeMap<u32, BTreeMap<String, SyscallStats>> {
+        &self.processes
+    }
+
+    /// Prints statistics as a table: total for all processes, then for each process
+    pub fn write_table(&self, wr: &mut dyn Write) -> std::io::Result<()> {
+        write_stats_table(&self.syscalls(), wr)?;
+        if self.processes.len() > 1 {
+            for (pid, syscalls) in &self.processes {
+                writeln!(wr)?;
+                writeln!(wr, "process {}:", pid)?;
+                write_stats_table(syscalls, wr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{}.{:06}", duration.as_secs(), duration.subsec_micros())
+}
+
+fn write_stats_table(
+    syscalls: &BTreeMap<String, SyscallStats>,
+    wr: &mut dyn Write,
+) -> std::io::Result<()> {
+    let mut rows = syscalls.iter().collect::<Vec<_>>();
+    // most expensive syscalls first
+    rows.sort_by(|(name_a, a), (name_b, b)| {
+        b.total_time
+            .cmp(&a.total_time)
+            .then(b.calls.cmp(&a.calls))
+            .then(name_a.cmp(name_b))
+    });
+    writeln!(
+        wr,
+        "{:>9} {:>9} {:>12} {:>12} {:>12}  {:<20} errors",
+        "calls", "failed", "total (s)", "avg (s)", "max (s)", "syscall"
+    )?;
+    let mut total = SyscallStats::default();
+    for (name, stats) in rows {
+        let errors = stats
+            .errors
+            .iter()
+            .map(|(&errno, count)| format!("{}:{}", errno_name(errno), count))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            wr,
+            "{:>9} {:>9} {:>12} {:>12} {:>12}  {:<20} {}",
+            stats.calls,
+            stats.error_count(),
+            format_duration(stats.total_time),
+            format_duration(stats.avg_time()),
+            format_duration(stats.max_time),
+            name,
+            errors
+        )?;
+        total.merge(stats);
+    }
+    writeln!(
+        wr,
+        "{:>9} {:>9} {:>12} {:>12} {:>12}  total",
+        total.calls,
+        total.error_count(),
+        format_duration(total.total_time),
+        format_duration(total.avg_time()),
+        format_duration(total.max_time),
+    )
+}
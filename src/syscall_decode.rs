@@ -3,9 +3,8 @@
 
 use crate::{
     magic::{
-        self,
         hir::{FieldTypeInfo, SyscallDef},
-        ty::{PrimitiveTy, Ty, Value},
+        ty::{ArrayTy, PrimitiveTy, StructTy, Ty, Value},
         Magic,
     },
     RawSyscall, Syscall,
@@ -18,15 +17,30 @@ use nix::{
     unistd::Pid,
 };
 
+/// Syscall stop at which decoding happens
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Stage {
+    Enter,
+    Exit,
+}
+
 pub(crate) struct Decoder<'a> {
     pub(crate) magic: &'a Magic,
     pub(crate) evaluated: Vec<Option<Value>>,
     pub(crate) raw: &'a RawSyscall,
     pub(crate) proc: Pid,
     pub(crate) syscall: &'a SyscallDef,
+    /// Already decoded fields of structs being decoded, innermost last
+    pub(crate) scopes: Vec<Vec<(String, Value)>>,
+    /// Bytes left for buffers of array with `#[total_len = ...]`, which is being decoded.
+    /// Contains None if total length is unknown (e.g. syscall failed)
+    pub(crate) buffer_budget: Option<Option<usize>>,
 }
 
 const MAX_LEN: usize = 4096;
+const MAX_ARRAY_LEN: usize = 256;
+const MAX_ZSTRING_ARRAY_LEN: usize = 1024;
+const MAX_ZSTRING_ARRAY_SIZE: usize = 65536;
 
 fn try_read_buf(ptr: usize, len: usize, proc: Pid) -> Option<Vec<u8>> {
     let mut out_buf = Vec::new();
@@ -73,6 +87,48 @@ fn try_read_zstring(ptr: usize, proc: Pid) -> Option<Vec<u8>> {
     Some(out_buf)
 }
 
+/// Reads NULL-terminated array of zero-terminated strings.
+/// Second returned value is `true` if limits were exceeded, and the array was not read completely.
+fn try_read_zstring_array(ptr: usize, proc: Pid) -> Option<(Vec<Vec<u8>>, bool)> {
+    let mut out = Vec::new();
+    let mut total_size = 0;
+    loop {
+        if out.len() == MAX_ZSTRING_ARRAY_LEN || total_size >= MAX_ZSTRING_ARRAY_SIZE {
+            return Some((out, true));
+        }
+        let item_ptr = try_read_buf(ptr + 8 * out.len(), 8, proc)?;
+        let item_ptr = read_scalar(&item_ptr, false) as usize;
+        if item_ptr == 0 {
+            return Some((out, false));
+        }
+        let item = try_read_zstring(item_ptr, proc)?;
+        total_size += item.len() + 1;
+        out.push(item);
+    }
+}
+
+/// Leaves only `size` lower bytes of `value`
+fn truncate(value: u64, size: usize) -> u64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1 << (8 * size)) - 1)
+    }
+}
+
+/// Reads little-endian integer of `data.len()` bytes
+fn read_scalar(data: &[u8], signed: bool) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..data.len()].copy_from_slice(data);
+    let value = u64::from_le_bytes(bytes);
+    let shift = 64 - 8 * data.len() as u32;
+    if signed && shift != 0 {
+        ((value << shift) as i64 >> shift) as u64
+    } else {
+        value
+    }
+}
+
 fn decode_error(value: u64) -> Option<Value> {
     let value = value as i64;
     if value >= 0 {
@@ -83,7 +139,7 @@ fn decode_error(value: u64) -> Option<Value> {
     if value <= ERRNO_MAX {
         return Some(Value::Error(
             value as i32,
-            nix::errno::Errno::from_i32(value as i32).desc().to_string(),
+            crate::errno_description(value as i32),
         ));
     }
     None
@@ -93,7 +149,16 @@ impl<'a> Decoder<'a> {
     fn resolve_path(&self, mut path: impl Iterator<Item = impl AsRef<str>>) -> Option<&Value> {
         let head = path.next()?;
         let head = head.as_ref();
+        // fields of innermost struct shadow syscall params
+        if let Some(scope) = self.scopes.last() {
+            if let Some((_, val)) = scope.iter().find(|(name, _)| name == head) {
+                return val.project(path);
+            }
+        }
         let mut field = None;
+        if head == "ret" {
+            field = Some(self.syscall.params_count());
+        }
         for fl in self.syscall.params() {
             let fl_info = fl.1;
             if fl_info.name == head {
@@ -108,6 +173,21 @@ impl<'a> Decoder<'a> {
         val.project(path)
     }
 
+    /// Resolves length, specified with `#[len = ...]` attribute
+    /// If several paths are given, minimum is used. If any of them can't be resolved
+    /// (e.g. syscall returned error), length is unknown.
+    fn resolve_len(&self, field_ty_info: &FieldTypeInfo) -> Option<usize> {
+        let mut res: Option<usize> = None;
+        for path in &field_ty_info.len_ref {
+            let len = match self.resolve_path(path.iter())? {
+                Value::Integral(len) if *len >= 0 => *len as usize,
+                _ => return None,
+            };
+            res = Some(res.map_or(len, |res| res.min(len)));
+        }
+        res
+    }
+
     fn do_decode(&mut self, ty: &Ty, value: u64, field_ty_info: &FieldTypeInfo) -> Value {
         match ty {
             Ty::Primitive(PrimitiveTy::Fd) => Value::Handle(value as u32, None),
@@ -119,26 +199,35 @@ impl<'a> Decoder<'a> {
                 }
                 result
             }
+            Ty::Primitive(PrimitiveTy::ZStringArray) => {
+                match try_read_zstring_array(value as usize, self.proc) {
+                    Some((items, truncated)) => {
+                        let items = items
+                            .iter()
+                            .map(|item| Value::String(String::from_utf8_lossy(item).to_string()))
+                            .collect();
+                        Value::Array(items, truncated)
+                    }
+                    None => Value::Unknown,
+                }
+            }
             Ty::Primitive(PrimitiveTy::Buffer) => {
-                let mut result = Value::Unknown;
-                let len_arg_path = &field_ty_info.len_ref;
-                if !len_arg_path.is_empty() {
-                    let len = self.resolve_path(len_arg_path.iter());
-                    if let Some(len) = len {
-                        if let Value::Integral(len) = len {
-                            let len = *len;
-                            if len >= 0 {
-                                let len = len as usize;
-                                let buf = try_read_buf(value as usize, len, self.proc);
-                                if let Some(buf) = buf {
-                                    result = Value::Buffer(buf.into_boxed_slice());
-                                }
-                            }
+                let mut len = self.resolve_len(field_ty_info);
+                if let Some(budget) = &mut self.buffer_budget {
+                    len = match (len, *budget) {
+                        (Some(len), Some(left)) => {
+                            let len = len.min(left);
+                            *budget = Some(left - len);
+                            Some(len)
                         }
-                    }
+                        _ => None,
+                    };
+                }
+                let buf = len.and_then(|len| try_read_buf(value as usize, len, self.proc));
+                match buf {
+                    Some(buf) => Value::Buffer(buf.into_boxed_slice()),
+                    None => Value::Unknown,
                 }
-
-                result
             }
             Ty::Primitive(PrimitiveTy::Signal) => {
                 let signal_id = value as i32;
@@ -148,8 +237,99 @@ impl<'a> Decoder<'a> {
                 Value::Signal(signal_id, signal_name.map(ToString::to_string))
             }
             Ty::Primitive(PrimitiveTy::Address) => Value::Address(value),
+            Ty::Struct(struct_ty) => {
+                match try_read_buf(value as usize, struct_ty.size, self.proc) {
+                    Some(data) => self.decode_struct(struct_ty, &data),
+                    None => Value::Unknown,
+                }
+            }
+            Ty::Flags(flags_ty) => {
+                let value = truncate(value, flags_ty.size);
+                let (names, unknown) = flags_ty.split(value);
+                Value::Flags(value, names, unknown)
+            }
+            Ty::Enum(enum_ty) => {
+                let value = truncate(value, enum_ty.size);
+                let name = enum_ty.variant_name(value);
+                Value::Enum(value as i64, name.map(ToString::to_string))
+            }
+            Ty::Array(array_ty) => match self.resolve_len(field_ty_info) {
+                Some(len) => {
+                    let budget = field_ty_info.total_len_ref.as_ref().map(|path| {
+                        match self.resolve_path(path.iter()) {
+                            Some(Value::Integral(len)) if *len >= 0 => Some(*len as usize),
+                            _ => None,
+                        }
+                    });
+                    let outer_budget = std::mem::replace(&mut self.buffer_budget, budget);
+                    let res = self.decode_array(array_ty, value, len);
+                    self.buffer_budget = outer_budget;
+                    res
+                }
+                None => Value::Unknown,
+            },
+            Ty::Null => Value::Unknown,
+        }
+    }
+
+    /// Decodes value of type `ty`, stored in memory as `data`
+    fn decode_in_memory(&mut self, ty: &Ty, data: &[u8], field_ty_info: &FieldTypeInfo) -> Value {
+        match ty {
+            Ty::Struct(struct_ty) => self.decode_struct(struct_ty, data),
             Ty::Null => Value::Unknown,
+            _ => {
+                let signed = matches!(ty, Ty::Primitive(prim) if prim.is_signed());
+                let value = read_scalar(data, signed);
+                self.do_decode(ty, value, field_ty_info)
+            }
+        }
+    }
+
+    fn decode_struct(&mut self, struct_ty: &StructTy, data: &[u8]) -> Value {
+        self.scopes.push(Vec::new());
+        for &i in &struct_ty.decode_order {
+            let field = &struct_ty.fields[i];
+            let ty = self.magic.resolve_ty(&field.ty_info.ty_name);
+            let field_data = &data[field.offset..field.offset + field.size];
+            let value = self.decode_in_memory(ty, field_data, &field.ty_info);
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .push((field.name.clone(), value));
         }
+        let fields = self.scopes.pop().unwrap();
+        // restore definition order
+        let mut fields = struct_ty
+            .decode_order
+            .iter()
+            .zip(fields)
+            .collect::<Vec<_>>();
+        fields.sort_by_key(|(i, _)| **i);
+        Value::Struct(fields.into_iter().map(|(_, field)| field).collect())
+    }
+
+    fn decode_array(&mut self, array_ty: &ArrayTy, ptr: u64, len: usize) -> Value {
+        let elem_ty = self.magic.resolve_ty(&array_ty.elem_ty_name);
+        let elem_size = match elem_ty.layout() {
+            Some((size, _)) if size != 0 => size,
+            _ => return Value::Unknown,
+        };
+        let count = len.min(MAX_ARRAY_LEN).min(MAX_LEN / elem_size);
+        let data = match try_read_buf(ptr as usize, count * elem_size, self.proc) {
+            Some(data) => data,
+            None => return Value::Unknown,
+        };
+        let elem_ty_info = FieldTypeInfo {
+            ty_name: array_ty.elem_ty_name.clone(),
+            len_ref: Vec::new(),
+            size: None,
+            total_len_ref: None,
+        };
+        let elems = data
+            .chunks(elem_size)
+            .map(|elem_data| self.decode_in_memory(elem_ty, elem_data, &elem_ty_info))
+            .collect();
+        Value::Array(elems, count < len)
     }
 
     fn do_decode_arg(&mut self, idx: usize) -> Value {
@@ -174,44 +354,47 @@ impl<'a> Decoder<'a> {
         self.do_decode(ty, value, &field.ty_info)
     }
 
-    fn decode_arg(&mut self, idx: usize) {
-        let val = self.do_decode_arg(idx);
-        let old = std::mem::replace(&mut self.evaluated[idx], Some(val));
-        assert!(old.is_none());
-    }
-
-    pub(crate) fn process(&mut self) -> Option<Syscall> {
-        let syscall_id = magic::hir::SyscallId(self.raw.syscall_id as u32);
-        let syscall_spec = match self.magic.lookup_syscall_by_id(syscall_id) {
-            Some(spec) => spec,
-            None => {
-                return None;
+    /// Decodes fields which are meaningful at `stage`.
+    /// `self.evaluated` may already contain values decoded on syscall entry:
+    /// on exit they are kept, unless field is output one.
+    pub(crate) fn process(&mut self, stage: Stage) -> Syscall {
+        let params_count = self.syscall.params_count();
+        self.evaluated
+            .resize_with(params_count + 1, Default::default);
+        for &i in &self.syscall.body.decode_order {
+            let dir = self.syscall.body.fields[i].dir;
+            let should_decode = match stage {
+                Stage::Enter => dir.is_in(),
+                // if entry was missed, input fields are decoded too
+                Stage::Exit => dir.is_out() || self.evaluated[i].is_none(),
+            };
+            if !should_decode {
+                continue;
             }
-        };
-
-        // TODO: .rev() is quick fix to ensure that we at first process size, and afterwards buffer
-        // proper solution is build topsort
-        for i in (0..self.syscall.params_count()).rev() {
-            self.decode_arg(i);
-        }
-        for _ in self.syscall.params_count()..self.evaluated.len() {
-            self.evaluated.pop();
+            let val = if i == params_count {
+                self.do_decode_ret()
+            } else {
+                self.do_decode_arg(i)
+            };
+            self.evaluated[i] = Some(val);
         }
-        let ret_val = self.do_decode_ret();
-        self.evaluated.push(Some(ret_val));
 
-        let evaluated = std::mem::replace(&mut self.evaluated, Vec::new());
-        //dbg!(&evaluated);
-        let args: Option<Vec<Value>> = evaluated.into_iter().collect();
-        let mut args = args?;
+        let mut args = self
+            .evaluated
+            .iter()
+            .map(|val| val.clone().unwrap_or(Value::Unknown))
+            .collect::<Vec<_>>();
         let ret = args
             .pop()
             .expect("args should contain at least return value");
-        Some(Syscall {
-            name: syscall_spec.name.clone(),
+        Syscall {
+            name: self.syscall.name.clone(),
             args,
-            ret: Some(ret),
+            ret: match stage {
+                Stage::Enter => None,
+                Stage::Exit => Some(ret),
+            },
             backtrace: None,
-        })
+        }
     }
 }
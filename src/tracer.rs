@@ -1,21 +1,150 @@
 use crate::{
+    filter::SyscallFilter,
+    inject::Injector,
     magic::{
         hir::{SyscallDef, SyscallId},
         Magic,
     },
-    syscall_decode::Decoder,
-    Event, EventPayload, RawSyscall, Settings, Syscall,
+    syscall_decode::{Decoder, Stage},
+    Event, EventPayload, RawSyscall, Settings, SpawnKind, Syscall, Timestamp, Value,
+    AUDIT_ARCH_X86_64,
 };
 use anyhow::Context;
 use nix::{
-    sys::{ptrace, wait::WaitStatus},
+    sys::{
+        ptrace,
+        signal::{SigSet, Signal},
+        wait::{WaitPidFlag, WaitStatus},
+    },
     unistd::Pid,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, convert::TryFrom, time::Duration};
 use tiny_nix_ipc::Socket;
 
+/// Signals, which request tracer to stop: it should detach from all tracees (or kill them)
+/// and exit
+fn stop_signals() -> SigSet {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals
+}
+
+/// Signals, which tracer waits for: stop signals and SIGCHLD, which is sent when tracee
+/// changes state. They must be blocked in tracer, so that they stay pending until
+/// `wait_signal` receives them, even if they arrive while tracer is busy
+pub(crate) fn tracer_signals() -> SigSet {
+    let mut signals = stop_signals();
+    signals.add(Signal::SIGCHLD);
+    signals
+}
+
+/// Receives one of pending `signals`. If `block` is true, waits for it, otherwise returns
+/// None if there are no such signals
+fn wait_signal(signals: &SigSet, block: bool) -> anyhow::Result<Option<Signal>> {
+    let no_wait = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let timeout = if block {
+        std::ptr::null()
+    } else {
+        &no_wait as *const libc::timespec
+    };
+    let res = unsafe { libc::sigtimedwait(signals.as_ref(), std::ptr::null_mut(), timeout) };
+    if res != -1 {
+        return Ok(Some(Signal::try_from(res).context("unexpected signal")?));
+    }
+    match nix::errno::Errno::last() {
+        nix::errno::Errno::EAGAIN | nix::errno::Errno::EINTR => Ok(None),
+        errno => Err(nix::Error::Sys(errno)).context("sigtimedwait() failed"),
+    }
+}
+
+/// Stops seized tracee, as with `ptrace(PTRACE_INTERRUPT, ...)`
+fn interrupt(tid: Pid) -> std::io::Result<()> {
+    // nix does not provide PTRACE_INTERRUPT wrapper
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_INTERRUPT,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    match res {
+        -1 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Stops running tracee and detaches from it, leaving it running.
+/// `seized` is true if tracee was attached using PTRACE_SEIZE and can be stopped
+/// with PTRACE_INTERRUPT; otherwise SIGSTOP is used.
+fn detach(tid: Pid, seized: bool) -> anyhow::Result<()> {
+    let res = if seized {
+        interrupt(tid)
+    } else {
+        match unsafe { libc::syscall(libc::SYS_tkill, tid.as_raw(), libc::SIGSTOP) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    };
+    match res {
+        Ok(()) => {}
+        // tracee has already exited
+        Err(err) if err.raw_os_error() == Some(libc::ESRCH) => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("failed to stop thread {}", tid)),
+    }
+    loop {
+        let wstatus = match nix::sys::wait::waitpid(tid, Some(nix::sys::wait::WaitPidFlag::__WALL))
+        {
+            Ok(wstatus) => wstatus,
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => return Ok(()),
+            Err(err) => return Err(err).context("waitpid() failed"),
+        };
+        let res = match wstatus {
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => return Ok(()),
+            // our SIGSTOP: suppress it
+            WaitStatus::Stopped(_, nix::sys::signal::Signal::SIGSTOP) if !seized => {
+                ptrace::detach(tid, None)
+            }
+            // signal-delivery-stop of other signal: it must be delivered
+            WaitStatus::Stopped(_, sig) if seized => ptrace::detach(tid, sig),
+            WaitStatus::Stopped(_, sig) => {
+                // our SIGSTOP is still pending, wait for it
+                ptrace::cont(tid, sig).ok();
+                continue;
+            }
+            // syscall-stop or ptrace-event-stop (including interrupt stop).
+            // For seized tracee, pending interrupt is discarded on detach
+            _ if seized => ptrace::detach(tid, None),
+            _ => {
+                ptrace::cont(tid, None).ok();
+                continue;
+            }
+        };
+        return match res {
+            Ok(()) | Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("failed to detach from thread {}", tid)),
+        };
+    }
+}
+
+#[derive(Default)]
 struct ChildInfo {
+    /// Thread group id, i.e. pid of process which thread belongs to
+    tgid: u32,
     in_syscall: bool,
+    /// Values decoded on syscall entry, they will be merged with output values on exit
+    entry_values: Option<(SyscallId, Vec<Option<Value>>)>,
+    /// Time of syscall entry, used to calculate syscall duration
+    entry_time: Option<Duration>,
+    /// Number of syscall being executed, if its entry was observed
+    entry_syscall_id: Option<u64>,
+    /// Error which should be returned from syscall being executed instead of its result
+    injected_errno: Option<i32>,
 }
 
 fn decode_syscall_args(regs: libc::user_regs_struct) -> RawSyscall {
@@ -23,6 +152,9 @@ fn decode_syscall_args(regs: libc::user_regs_struct) -> RawSyscall {
         syscall_id: 0,
         args: [0; 6],
         ret: 0,
+        arch: AUDIT_ARCH_X86_64,
+        ip: regs.rip,
+        sp: regs.rsp,
     };
     out.ret = regs.rax;
     out.syscall_id = regs.orig_rax;
@@ -35,23 +167,107 @@ fn decode_syscall_args(regs: libc::user_regs_struct) -> RawSyscall {
     out
 }
 
+/// Returns None if kernel does not support PTRACE_GET_SYSCALL_INFO (it was added in Linux 5.3)
+fn get_syscall_info(pid: Pid) -> anyhow::Result<Option<libc::ptrace_syscall_info>> {
+    // struct has private padding fields
+    let mut info: libc::ptrace_syscall_info = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GET_SYSCALL_INFO,
+            pid.as_raw(),
+            std::mem::size_of_val(&info),
+            &mut info as *mut libc::ptrace_syscall_info,
+        )
+    };
+    if res == -1 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::EIO) {
+            return Ok(None);
+        }
+        return Err(err).context("ptrace(PTRACE_GET_SYSCALL_INFO) failed");
+    }
+    Ok(Some(info))
+}
+
+/// Updates `raw` with data reported by PTRACE_GET_SYSCALL_INFO.
+/// Returns true if tracee is entering syscall, false if it is exiting, or None if unknown.
+fn apply_syscall_info(raw: &mut RawSyscall, info: &libc::ptrace_syscall_info) -> Option<bool> {
+    let entering = match info.op {
+        libc::PTRACE_SYSCALL_INFO_ENTRY => {
+            let entry = unsafe { &info.u.entry };
+            raw.syscall_id = entry.nr;
+            raw.args = entry.args;
+            true
+        }
+        libc::PTRACE_SYSCALL_INFO_SECCOMP => {
+            let seccomp = unsafe { &info.u.seccomp };
+            raw.syscall_id = seccomp.nr;
+            raw.args = seccomp.args;
+            true
+        }
+        libc::PTRACE_SYSCALL_INFO_EXIT => {
+            // syscall number and arguments are only available from registers
+            raw.ret = unsafe { info.u.exit.sval } as u64;
+            false
+        }
+        _ => return None,
+    };
+    raw.arch = info.arch;
+    raw.ip = info.instruction_pointer;
+    raw.sp = info.stack_pointer;
+    Some(entering)
+}
+
+/// Returns path to executable of process, or empty string if it is not available
+fn read_exe_path(pid: u32) -> String {
+    std::fs::read_link(format!("/proc/{}/exe", pid))
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns thread group id of thread, or `tid` itself if it is unknown
+fn read_tgid(tid: u32) -> u32 {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", tid)).unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse().ok())
+        .unwrap_or(tid)
+}
+
+/// Returns command line arguments of process
+fn read_cmdline(pid: u32) -> Vec<String> {
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+    let mut args = cmdline
+        .split(|&b| b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect::<Vec<_>>();
+    // arguments are terminated (not separated) by zero byte, so last item is always empty
+    args.pop();
+    args
+}
+
+/// Returns decoded syscall and values of all fields, decoded so far
 fn process_syscall(
     raw: &RawSyscall,
     proc: Pid,
     magic: &Magic,
     def: &SyscallDef,
-) -> Option<Syscall> {
-    let mut evaluated = Vec::new();
-    evaluated.resize_with(6, Default::default);
+    stage: Stage,
+    evaluated: Vec<Option<Value>>,
+) -> (Syscall, Vec<Option<Value>>) {
     let mut syscall_decoder = Decoder {
         evaluated,
-        magic: &magic,
+        magic,
         proc,
         raw,
         syscall: def,
+        scopes: Vec::new(),
+        buffer_budget: None,
     };
 
-    syscall_decoder.process()
+    let syscall = syscall_decoder.process(stage);
+    (syscall, syscall_decoder.evaluated)
 }
 
 fn captute_backtrace(proc: Pid) -> anyhow::Result<crate::backtrace::Backtrace> {
@@ -93,43 +309,152 @@ fn captute_backtrace(proc: Pid) -> anyhow::Result<crate::backtrace::Backtrace> {
     Ok(bt)
 }
 
+/// Seizes all threads of already running process and interrupts them.
+/// Returns ids of seized threads.
+fn seize_threads(pid: Pid, options: ptrace::Options) -> anyhow::Result<Vec<u32>> {
+    let task_dir = format!("/proc/{}/task", pid);
+    let mut seized = Vec::new();
+    // process can spawn new threads while we attach to existing, so repeat until
+    // no new threads appear. Threads spawned after that are traced because of PTRACE_O_TRACECLONE
+    loop {
+        let mut new_threads = false;
+        let tasks = std::fs::read_dir(&task_dir)
+            .with_context(|| format!("failed to list threads of process {}", pid))?;
+        for task in tasks {
+            let task =
+                task.with_context(|| format!("failed to list threads of process {}", pid))?;
+            let tid = match task.file_name().to_str().and_then(|name| name.parse().ok()) {
+                Some(tid) => tid,
+                None => continue,
+            };
+            if seized.contains(&tid) {
+                continue;
+            }
+            match ptrace::seize(Pid::from_raw(tid as i32), options) {
+                Ok(()) => {}
+                // thread has already exited
+                Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) if tid != pid.as_raw() as u32 => {
+                    continue
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| format!("failed to attach to thread {}", tid))
+                }
+            }
+            interrupt(Pid::from_raw(tid as i32))
+                .with_context(|| format!("failed to interrupt thread {}", tid))?;
+            seized.push(tid);
+            new_threads = true;
+        }
+        if !new_threads {
+            break;
+        }
+    }
+    Ok(seized)
+}
+
 pub(crate) unsafe fn parent(
     mut out: Socket,
     settings: Settings,
     magic: &Magic,
+    filter: &SyscallFilter,
+    injector: &Injector,
+    use_seccomp: bool,
+    attach_to: Option<Pid>,
 ) -> anyhow::Result<()> {
     let pid_children = Pid::from_raw(-1);
-    let waitflag = Some(nix::sys::wait::WaitPidFlag::__WALL);
+    let waitflag = Some(WaitPidFlag::__WALL | WaitPidFlag::WNOHANG);
     let mut children: HashMap<u32, ChildInfo> = HashMap::new();
+    // (tgid, syscall number) -> number of calls, which can be affected by injection rules.
+    // Calls are counted for whole process, so that all its threads share counters
+    let mut invocations: HashMap<(u32, u64), u64> = HashMap::new();
     let mut first_iteration = true;
+    // cleared if kernel turns out to be too old
+    let mut syscall_info_supported = true;
+    let mut options = ptrace::Options::PTRACE_O_TRACESYSGOOD
+        | ptrace::Options::PTRACE_O_TRACEFORK
+        | ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACEVFORK
+        | ptrace::Options::PTRACE_O_TRACEEXEC;
+    // tracee under seccomp filter can not run without tracer, so it is never left running
+    let kill_on_exit = settings.kill_on_exit || use_seccomp;
+    if kill_on_exit {
+        options |= ptrace::Options::PTRACE_O_EXITKILL;
+    }
+    if use_seccomp {
+        options |= ptrace::Options::PTRACE_O_TRACESECCOMP;
+    }
+    if let Some(pid) = attach_to {
+        for tid in seize_threads(pid, options)? {
+            let info = ChildInfo {
+                tgid: pid.as_raw() as u32,
+                ..ChildInfo::default()
+            };
+            children.insert(tid, info);
+            let event = Event {
+                time: Timestamp::now(),
+                pid: tid,
+                tgid: pid.as_raw() as u32,
+                payload: EventPayload::Attach,
+            };
+            out.send_json(&event, None)
+                .map_err(|err| anyhow::anyhow!("{}", err))
+                .context("failed to send event")?;
+        }
+        first_iteration = false;
+    }
+    // stop signals are checked before each waitpid(), because tracee can generate
+    // events faster than they are processed
+    let stop_signals = stop_signals();
+    let tracer_signals = tracer_signals();
+    let mut stop_requested = false;
     while !children.is_empty() || first_iteration {
+        if stop_requested || wait_signal(&stop_signals, false)?.is_some() {
+            for &tid in children.keys() {
+                let tid = Pid::from_raw(tid as i32);
+                if kill_on_exit {
+                    nix::sys::signal::kill(tid, Signal::SIGKILL).ok();
+                } else if let Err(err) = detach(tid, attach_to.is_some()) {
+                    eprintln!("{:?}", err);
+                }
+            }
+            break;
+        }
+        let wstatus = match nix::sys::wait::waitpid(pid_children, waitflag) {
+            Ok(WaitStatus::StillAlive) => {
+                // SIGCHLD is pending if tracee changed state after waitpid()
+                let sig = wait_signal(&tracer_signals, true)?;
+                stop_requested = sig.is_some_and(|sig| stop_signals.contains(sig));
+                continue;
+            }
+            Ok(wstatus) => wstatus,
+            Err(err) => return Err(err).context("waitpid() failed"),
+        };
+        let now = Timestamp::now();
         first_iteration = false;
-        let wstatus =
-            nix::sys::wait::waitpid(pid_children, waitflag).context("waitpid() failed")?;
-        let pid = wstatus.pid().unwrap().as_raw() as u32; // we don't use WNOHANG
+        let pid = wstatus.pid().unwrap().as_raw() as u32; // StillAlive is handled above
         let child_known = children.contains_key(&pid);
+        let tgid = children
+            .get(&pid)
+            .map_or_else(|| read_tgid(pid), |info| info.tgid);
         // None, if child should not be resumed
         // Some(None), if child should be resumed without signalling
         // Some(sig_id), if child should be  resumed, and sig_id (if non-null) will be injected
         let mut should_resume = Some(None);
         let event = match (child_known, wstatus) {
             (false, _) => {
-                ptrace::setoptions(
-                    Pid::from_raw(pid as i32),
-                    ptrace::Options::PTRACE_O_TRACESYSGOOD
-                        | ptrace::Options::PTRACE_O_EXITKILL
-                        | ptrace::Options::PTRACE_O_TRACEFORK
-                        | ptrace::Options::PTRACE_O_TRACECLONE
-                        | ptrace::Options::PTRACE_O_TRACEVFORK
-                        | ptrace::Options::PTRACE_O_TRACEEXEC,
-                )
-                .context("ptrace setoptions failed")?;
-
-                let new_child_info = ChildInfo { in_syscall: false };
-                children.insert(pid, new_child_info);
+                ptrace::setoptions(Pid::from_raw(pid as i32), options)
+                    .context("ptrace setoptions failed")?;
+
+                let info = ChildInfo {
+                    tgid,
+                    ..ChildInfo::default()
+                };
+                children.insert(pid, info);
 
                 let event = Event {
+                    time: now,
                     pid,
+                    tgid,
                     payload: EventPayload::Attach,
                 };
 
@@ -138,71 +463,192 @@ pub(crate) unsafe fn parent(
             (true, WaitStatus::Exited(_, exit_code)) => {
                 let ev_payload = EventPayload::Exit(exit_code);
                 let ev = Event {
+                    time: now,
                     payload: ev_payload,
                     pid,
+                    tgid,
                 };
+                // leader's exit is reported after all other threads have exited
+                invocations.retain(|&(tgid, _), _| tgid != pid);
                 children.remove(&pid);
                 should_resume = None;
                 Some(ev)
             }
-            (true, WaitStatus::PtraceSyscall(_)) => {
-                let cur_info = children.get(&pid).unwrap(); // it's guaranteed here that get() returns Some
-                let started_syscall = !cur_info.in_syscall;
-                let new_info = ChildInfo {
-                    in_syscall: started_syscall,
+            (true, WaitStatus::Signaled(_, sig, core_dumped)) => {
+                let ev = Event {
+                    time: now,
+                    payload: EventPayload::Killed {
+                        signal: sig as i32,
+                        core_dumped,
+                    },
+                    pid,
+                    tgid,
                 };
-                children.insert(pid, new_info);
+                // leader's exit is reported after all other threads have exited
+                invocations.retain(|&(tgid, _), _| tgid != pid);
+                children.remove(&pid);
+                should_resume = None;
+                Some(ev)
+            }
+            // seccomp stop is reported instead of syscall entry stop, if seccomp filter is used
+            (true, WaitStatus::PtraceSyscall(_))
+            | (true, WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_SECCOMP)) => {
                 let regs = nix::sys::ptrace::getregs(Pid::from_raw(pid as i32))
                     .context("ptrace getregs failed")?;
-                let params = decode_syscall_args(regs);
-                let def = magic.lookup_syscall_by_id(SyscallId(params.syscall_id as u32));
-                let child_pid = Pid::from_raw(pid as i32);
-                let mut decoded_params = match def {
-                    Some(def) => process_syscall(&params, child_pid, magic, def),
-                    None => None,
-                };
-                decoded_params.as_mut().map(|p| {
-                    // attach backtrace if requested
-                    if settings.capture_backtrace {
-                        match captute_backtrace(child_pid) {
-                            Ok(bt) => p.backtrace = Some(bt),
-                            Err(err) => {
-                                eprintln!("failed to capture backtrace: {:?}", err);
-                            }
-                        }
+                let mut params = decode_syscall_args(regs);
+                let mut started_syscall = None;
+                if syscall_info_supported {
+                    match get_syscall_info(Pid::from_raw(pid as i32))? {
+                        Some(info) => started_syscall = apply_syscall_info(&mut params, &info),
+                        None => syscall_info_supported = false,
                     }
-                });
+                }
+                // it's guaranteed here that get() returns Some
+                let cur_info = children.get_mut(&pid).unwrap();
+                // without PTRACE_GET_SYSCALL_INFO, we assume that entry and exit stops alternate
+                let started_syscall = started_syscall.unwrap_or(!cur_info.in_syscall);
+                cur_info.in_syscall = started_syscall;
+                let entry_values = cur_info.entry_values.take();
+                let entry_time = cur_info.entry_time.take();
+                let entry_syscall_id = cur_info.entry_syscall_id.take();
                 if started_syscall {
-                    decoded_params.as_mut().map(|p| {
-                        // Not provide return value, because it doesn't exist yes
-                        p.ret = None;
-                    });
-                    let ev_payload = EventPayload::Sysenter {
-                        raw: params,
-                        decoded: decoded_params,
-                    };
-                    let ev = Event {
-                        pid,
-                        payload: ev_payload,
+                    cur_info.entry_syscall_id = Some(params.syscall_id);
+                } else if let Some(entry_syscall_id) = entry_syscall_id {
+                    // orig_rax can be changed during syscall, e.g. rt_sigreturn sets it to -1
+                    params.syscall_id = entry_syscall_id;
+                }
+                let syscall_id = SyscallId(params.syscall_id as u32);
+                let child_pid = Pid::from_raw(pid as i32);
+                let injected = if started_syscall {
+                    let errno = if params.arch == AUDIT_ARCH_X86_64 && injector.handles(&syscall_id)
+                    {
+                        let invocation = invocations
+                            .entry((cur_info.tgid, params.syscall_id))
+                            .or_default();
+                        *invocation += 1;
+                        injector.errno(&syscall_id, *invocation)
+                    } else {
+                        None
                     };
-                    match def {
-                        Some(def) if def.strategy.on_enter => Some(ev),
-                        None => Some(ev),
-                        _ => None,
+                    if errno.is_some() {
+                        // kernel skips syscall with invalid number, and reports exit stop as usual
+                        let mut regs = regs;
+                        regs.orig_rax = u64::MAX;
+                        ptrace::setregs(child_pid, regs).context("failed to skip syscall")?;
                     }
+                    cur_info.injected_errno = errno;
+                    errno.is_some()
+                } else if let Some(errno) = cur_info.injected_errno.take() {
+                    let mut regs = regs;
+                    regs.rax = -(errno as i64) as u64;
+                    ptrace::setregs(child_pid, regs).context("failed to inject error")?;
+                    params.ret = regs.rax;
+                    true
                 } else {
-                    let ev_payload = EventPayload::Sysexit {
-                        raw: params,
-                        decoded: decoded_params,
+                    false
+                };
+                if !filter.matches(&syscall_id) {
+                    // syscall is not interesting: skip decoding and don't report it
+                    None
+                } else {
+                    let def = if params.arch == AUDIT_ARCH_X86_64 {
+                        magic.lookup_syscall_by_id(syscall_id.clone())
+                    } else {
+                        // magic only describes x86_64 syscalls
+                        None
                     };
-                    let ev = Event {
-                        pid,
-                        payload: ev_payload,
+                    let mut decoded_params = match def {
+                        Some(def) if started_syscall => {
+                            let (syscall, values) = process_syscall(
+                                &params,
+                                child_pid,
+                                magic,
+                                def,
+                                Stage::Enter,
+                                Vec::new(),
+                            );
+                            children.get_mut(&pid).unwrap().entry_values =
+                                Some((syscall_id, values));
+                            Some(syscall)
+                        }
+                        Some(def) => {
+                            let values = match entry_values {
+                                Some((entry_syscall_id, values))
+                                    if entry_syscall_id == syscall_id =>
+                                {
+                                    values
+                                }
+                                _ => Vec::new(),
+                            };
+                            let (syscall, _) = process_syscall(
+                                &params,
+                                child_pid,
+                                magic,
+                                def,
+                                Stage::Exit,
+                                values,
+                            );
+                            Some(syscall)
+                        }
+                        None => None,
                     };
-                    match def {
-                        Some(def) if def.strategy.on_exit => Some(ev),
-                        None => Some(ev),
-                        _ => None,
+                    if let Some(p) = decoded_params.as_mut() {
+                        // attach backtrace if requested
+                        if settings.capture_backtrace {
+                            match captute_backtrace(child_pid) {
+                                Ok(bt) => p.backtrace = Some(bt),
+                                Err(err) => {
+                                    eprintln!("failed to capture backtrace: {:?}", err);
+                                }
+                            }
+                        }
+                    }
+                    if started_syscall {
+                        children.get_mut(&pid).unwrap().entry_time = Some(now.monotonic);
+                        let ev_payload = EventPayload::Sysenter {
+                            raw: params,
+                            decoded: decoded_params,
+                            injected,
+                        };
+                        let ev = Event {
+                            time: now,
+                            pid,
+                            tgid,
+                            payload: ev_payload,
+                        };
+                        match def {
+                            Some(def) if def.strategy.on_enter || settings.report_all_stops => {
+                                Some(ev)
+                            }
+                            None => Some(ev),
+                            _ => None,
+                        }
+                    } else {
+                        let ev_payload = EventPayload::Sysexit {
+                            raw: params,
+                            decoded: decoded_params,
+                            duration: entry_time
+                                .and_then(|entry_time| now.monotonic.checked_sub(entry_time)),
+                            injected,
+                        };
+                        let ev = Event {
+                            time: now,
+                            pid,
+                            tgid,
+                            payload: ev_payload,
+                        };
+                        // injected error is reported even if syscall result is not interesting
+                        match def {
+                            Some(def)
+                                if def.strategy.on_exit
+                                    || injected
+                                    || settings.report_all_stops =>
+                            {
+                                Some(ev)
+                            }
+                            None => Some(ev),
+                            _ => None,
+                        }
                     }
                 }
             }
@@ -212,10 +658,60 @@ pub(crate) unsafe fn parent(
                     decoded: sig.as_str().to_string(),
                 };
 
-                let ev = Event { payload, pid };
+                let ev = Event {
+                    payload,
+                    pid,
+                    tgid,
+                    time: now,
+                };
                 should_resume = Some(Some(sig));
                 Some(ev)
             }
+            (true, WaitStatus::PtraceEvent(_, _, event_id))
+                if event_id == libc::PTRACE_EVENT_FORK
+                    || event_id == libc::PTRACE_EVENT_VFORK
+                    || event_id == libc::PTRACE_EVENT_CLONE =>
+            {
+                let kind = match event_id {
+                    libc::PTRACE_EVENT_FORK => SpawnKind::Fork,
+                    libc::PTRACE_EVENT_VFORK => SpawnKind::Vfork,
+                    _ => SpawnKind::Clone,
+                };
+                let child = ptrace::getevent(Pid::from_raw(pid as i32))
+                    .context("failed to get new child pid")?;
+                Some(Event {
+                    time: now,
+                    payload: EventPayload::Spawn {
+                        parent: pid,
+                        child: child as u32,
+                        kind,
+                    },
+                    pid,
+                    tgid,
+                })
+            }
+            (true, WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXEC)) => {
+                let old_pid = ptrace::getevent(Pid::from_raw(pid as i32))
+                    .context("failed to get pid of thread which called execve")?
+                    as u32;
+                if old_pid != pid {
+                    // non-leader thread called execve: other threads are gone,
+                    // and it took thread group leader's pid
+                    if let Some(info) = children.remove(&old_pid) {
+                        children.insert(pid, info);
+                    }
+                }
+                Some(Event {
+                    time: now,
+                    payload: EventPayload::Exec {
+                        old_pid,
+                        path: read_exe_path(pid),
+                        argv: read_cmdline(pid),
+                    },
+                    pid,
+                    tgid,
+                })
+            }
             (true, WaitStatus::PtraceEvent(_, _sigtrap, _event_id)) => None,
             (true, other) => {
                 eprintln!("unknown WaitStatus: {:?}", other);
@@ -229,11 +725,21 @@ pub(crate) unsafe fn parent(
         }
         if let Some(sig) = should_resume {
             // resume again, if child hasn't finished yet
-            ptrace::syscall(wstatus.pid().unwrap(), sig).context("failed to resume child")?;
+            let child_pid = wstatus.pid().unwrap();
+            // with seccomp, kernel stops child on interesting syscalls itself,
+            // so we only have to request syscall exit stop
+            let wait_syscall_exit = children.get(&pid).is_some_and(|info| info.in_syscall);
+            if use_seccomp && !wait_syscall_exit {
+                ptrace::cont(child_pid, sig).context("failed to resume child")?;
+            } else {
+                ptrace::syscall(child_pid, sig).context("failed to resume child")?;
+            }
         }
     }
     let event = Event {
+        time: Timestamp::now(),
         pid: 0,
+        tgid: 0,
         payload: EventPayload::Eos,
     };
     out.send_json(&event, None)